@@ -0,0 +1,248 @@
+use crate::masking::RegexWrapper;
+use crate::response::Response;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+use tracing::{debug, info};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum Assertion {
+    Status {
+        equals: u16,
+    },
+    Header {
+        name: String,
+        equals: String,
+    },
+    JsonPointer {
+        pointer: String,
+        equals: Value,
+    },
+    JsonPath {
+        path: String,
+        equals: Value,
+    },
+    Regex {
+        pattern: RegexWrapper,
+        /// Match against this header instead of the body
+        header: Option<String>,
+    },
+    ResponseTime {
+        max_ms: u64,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct AssertionOutcome {
+    pub description: String,
+    /// Why the assertion failed, or `None` if it passed
+    pub failure: Option<String>,
+}
+
+impl AssertionOutcome {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Assertion::Status { equals } => write!(f, "status == {}", equals),
+            Assertion::Header { name, equals } => write!(f, "header {} == {:?}", name, equals),
+            Assertion::JsonPointer { pointer, equals } => write!(f, "{} == {}", pointer, equals),
+            Assertion::JsonPath { path, equals } => write!(f, "{} == {}", path, equals),
+            Assertion::Regex {
+                pattern,
+                header: Some(name),
+            } => write!(f, "header {} matches /{}/", name, pattern.0),
+            Assertion::Regex {
+                pattern,
+                header: None,
+            } => write!(f, "body matches /{}/", pattern.0),
+            Assertion::ResponseTime { max_ms } => write!(f, "response time <= {}ms", max_ms),
+        }
+    }
+}
+
+impl Assertion {
+    /// Check the assertion against a response
+    pub fn check(&self, response: &Response) -> AssertionOutcome {
+        let failure = match self {
+            Assertion::Status { equals } => {
+                let actual = response.status.as_u16();
+                (actual != *equals).then(|| format!("got {}", actual))
+            }
+            Assertion::Header { name, equals } => match header(response, name) {
+                Ok(actual) if actual == *equals => None,
+                Ok(actual) => Some(format!("got {:?}", actual)),
+                Err(failure) => Some(failure),
+            },
+            Assertion::JsonPointer { pointer, equals } => match response.json() {
+                Ok(json) => match json.pointer(pointer) {
+                    Some(actual) if actual == equals => None,
+                    Some(actual) => Some(format!("got {}", actual)),
+                    None => Some("pointer not found".to_string()),
+                },
+                Err(error) => Some(error.to_string()),
+            },
+            Assertion::JsonPath { path, equals } => match response.json() {
+                Ok(json) => match jsonpath_lib::select(&json, path) {
+                    Ok(matches) if matches.is_empty() => Some("path not found".to_string()),
+                    // A single match is compared as is, several are compared as an array
+                    Ok(matches) => {
+                        let actual = match matches.as_slice() {
+                            [single] => (*single).clone(),
+                            _ => Value::Array(matches.into_iter().cloned().collect()),
+                        };
+                        (actual != *equals).then(|| format!("got {}", actual))
+                    }
+                    Err(error) => Some(format!("invalid JSONPath: {}", error)),
+                },
+                Err(error) => Some(error.to_string()),
+            },
+            Assertion::Regex {
+                pattern,
+                header: name,
+            } => {
+                let subject = match name {
+                    Some(name) => header(response, name),
+                    None => Ok(response.text.clone()),
+                };
+                match subject {
+                    Ok(subject) if pattern.0.is_match(&subject) => None,
+                    Ok(_) => Some("no match".to_string()),
+                    Err(failure) => Some(failure),
+                }
+            }
+            Assertion::ResponseTime { max_ms } => {
+                let actual = response.elapsed.as_millis();
+                (actual > u128::from(*max_ms)).then(|| format!("took {}ms", actual))
+            }
+        };
+
+        debug!(assertion = %self, ?failure, "Checked assertion");
+        AssertionOutcome {
+            description: self.to_string(),
+            failure,
+        }
+    }
+}
+
+/// Check every assertion defined on the request that produced `response`
+pub fn check_all(response: &Response) -> Vec<AssertionOutcome> {
+    let outcomes: Vec<_> = response
+        .request
+        .assertions
+        .iter()
+        .map(|assertion| assertion.check(response))
+        .collect();
+
+    info!(
+        "{} of {} assertions passed for {}",
+        outcomes.iter().filter(|outcome| outcome.passed()).count(),
+        outcomes.len(),
+        response.request.name
+    );
+    outcomes
+}
+
+fn header(response: &Response, name: &str) -> Result<String, String> {
+    response
+        .headers
+        .get(name)
+        .ok_or_else(|| "header not found".to_string())?
+        .to_str()
+        .map(|value| value.to_string())
+        .map_err(|error| error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderMap;
+    use std::time::Duration;
+
+    fn response(assertions: &str) -> Response {
+        let toml = format!(
+            r#"
+            name = "Get User"
+            method = "GET"
+            url = "https://example.com/user"
+            {}
+            "#,
+            assertions
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "application/json".parse().unwrap());
+
+        let mut response = Response::fixture(
+            &toml,
+            200,
+            headers,
+            r#"{"user": {"id": 7, "roles": ["admin", "dev"]}}"#,
+        );
+        response.elapsed = Duration::from_millis(120);
+        response
+    }
+
+    #[test]
+    fn test_passing_assertions() {
+        let response = response(
+            r#"
+            [[assertions]]
+            type = "Status"
+            equals = 200
+            [[assertions]]
+            type = "Header"
+            name = "content-type"
+            equals = "application/json"
+            [[assertions]]
+            type = "JsonPointer"
+            pointer = "/user/id"
+            equals = 7
+            [[assertions]]
+            type = "JsonPath"
+            path = "$.user.roles[*]"
+            equals = ["admin", "dev"]
+            [[assertions]]
+            type = "Regex"
+            pattern = '"id":\s*\d+'
+            [[assertions]]
+            type = "ResponseTime"
+            max_ms = 500
+            "#,
+        );
+
+        let outcomes = check_all(&response);
+
+        assert_eq!(outcomes.len(), 6);
+        assert!(outcomes.iter().all(|outcome| outcome.passed()));
+    }
+
+    #[test]
+    fn test_failing_assertions_explain_why() {
+        let response = response(
+            r#"
+            [[assertions]]
+            type = "Status"
+            equals = 201
+            [[assertions]]
+            type = "JsonPointer"
+            pointer = "/user/name"
+            equals = "Ada"
+            [[assertions]]
+            type = "ResponseTime"
+            max_ms = 100
+            "#,
+        );
+
+        let failures: Vec<_> = check_all(&response)
+            .into_iter()
+            .filter_map(|outcome| outcome.failure)
+            .collect();
+
+        assert_eq!(failures, ["got 200", "pointer not found", "took 120ms"]);
+    }
+}
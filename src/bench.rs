@@ -0,0 +1,266 @@
+use crate::executor::{ExecutionError, Executor, Variables};
+use crate::options::BenchOptions;
+use console::style;
+use futures::stream::{self, StreamExt};
+use reqwest::StatusCode;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Requests sent when neither a count nor a duration is given
+const DEFAULT_COUNT: usize = 100;
+
+/// The outcome of a single benchmark request
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub latency: Duration,
+    /// The response status, or `None` if no response was received
+    pub status: Option<StatusCode>,
+}
+
+impl Sample {
+    fn is_error(&self) -> bool {
+        self.status
+            .is_none_or(|status| status.is_client_error() || status.is_server_error())
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub request: String,
+    pub concurrency: usize,
+    pub elapsed: Duration,
+    samples: Vec<Sample>,
+}
+
+impl Report {
+    pub fn new(
+        request: &str,
+        concurrency: usize,
+        elapsed: Duration,
+        mut samples: Vec<Sample>,
+    ) -> Self {
+        samples.sort_by_key(|sample| sample.latency);
+        Self {
+            request: request.to_string(),
+            concurrency,
+            elapsed,
+            samples,
+        }
+    }
+
+    /// Requests completed per second
+    pub fn throughput(&self) -> f64 {
+        self.samples.len() as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn errors(&self) -> usize {
+        self.samples
+            .iter()
+            .filter(|sample| sample.is_error())
+            .count()
+    }
+
+    /// Share of requests that failed or got a 4xx/5xx status, from 0 to 1
+    pub fn error_rate(&self) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        self.errors() as f64 / self.samples.len() as f64
+    }
+
+    /// The latency below which `percentile` percent of requests completed,
+    /// using the nearest-rank method
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (percentile / 100.0 * self.samples.len() as f64).ceil() as usize;
+        self.samples[rank.clamp(1, self.samples.len()) - 1].latency
+    }
+
+    /// How many responses had each status, with failed requests under `None`
+    pub fn statuses(&self) -> BTreeMap<Option<StatusCode>, usize> {
+        let mut statuses = BTreeMap::new();
+        for sample in &self.samples {
+            *statuses.entry(sample.status).or_default() += 1;
+        }
+        statuses
+    }
+
+    /// Print the report, styled unless `raw` is set
+    pub fn render(&self, raw: bool) {
+        if raw {
+            print!("{}", self);
+            return;
+        }
+
+        println!(
+            "{} {}",
+            style(" BENCH ").on_blue().black(),
+            style(&self.request).bold()
+        );
+        println!(
+            "{} requests in {:.2?} at concurrency {} ({:.1} req/s)",
+            self.samples.len(),
+            self.elapsed,
+            self.concurrency,
+            self.throughput()
+        );
+
+        let errors = format!(
+            "{} errors ({:.1}%)",
+            self.errors(),
+            self.error_rate() * 100.0
+        );
+        if self.errors() > 0 {
+            println!("{}", style(errors).red());
+        } else {
+            println!("{}", style(errors).green());
+        }
+
+        println!("{}", style(self.latency_line()).bold());
+        for (status, count) in self.statuses() {
+            println!("  {:>5}  {}", status_label(status), count);
+        }
+    }
+
+    fn latency_line(&self) -> String {
+        format!(
+            "p50 {:.2?}  p90 {:.2?}  p99 {:.2?}  max {:.2?}",
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(99.0),
+            self.samples
+                .last()
+                .map_or(Duration::ZERO, |sample| sample.latency)
+        )
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "request: {}", self.request)?;
+        writeln!(f, "requests: {}", self.samples.len())?;
+        writeln!(f, "elapsed: {:.2?}", self.elapsed)?;
+        writeln!(f, "concurrency: {}", self.concurrency)?;
+        writeln!(f, "throughput: {:.1} req/s", self.throughput())?;
+        writeln!(
+            f,
+            "errors: {} ({:.1}%)",
+            self.errors(),
+            self.error_rate() * 100.0
+        )?;
+        writeln!(f, "latency: {}", self.latency_line())?;
+        for (status, count) in self.statuses() {
+            writeln!(f, "status {}: {}", status_label(status), count)?;
+        }
+        Ok(())
+    }
+}
+
+fn status_label(status: Option<StatusCode>) -> String {
+    status.map_or("error".to_string(), |status| status.as_str().to_string())
+}
+
+/// Resolve a request's placeholders once, then send it repeatedly and report
+/// how the server held up. Retries are not applied, so that every attempt
+/// counts.
+pub async fn run(
+    executor: &Executor,
+    options: &BenchOptions,
+    raw: bool,
+) -> Result<(), ExecutionError> {
+    // Cycles can't be resolved, so report them before resolving anything
+    executor.plan(Some(&options.request))?;
+    let request = executor.find_request(&options.request)?;
+    let prepared = executor.prepare_request(request, &Variables::new()).await?;
+
+    let count = match (options.count, options.duration) {
+        (Some(count), _) => count,
+        (None, Some(_)) => usize::MAX,
+        (None, None) => DEFAULT_COUNT,
+    };
+    let deadline = options
+        .duration
+        .map(|seconds| Instant::now() + Duration::from_secs(seconds));
+    let concurrency = options.concurrency.max(1);
+
+    let started = Instant::now();
+    let samples: Vec<Sample> = stream::iter(0..count)
+        .take_while(|_| async move { deadline.is_none_or(|deadline| Instant::now() < deadline) })
+        .map(|_| {
+            let builder = executor.build_request(request, prepared.clone());
+            async move {
+                let sent = Instant::now();
+                let status = match builder?.send().await {
+                    // Read the body, so the latency covers the whole response
+                    Ok(response) => {
+                        let status = response.status();
+                        match response.bytes().await {
+                            Ok(_) => Some(status),
+                            Err(error) => {
+                                warn!("Reading the response failed: {}", error);
+                                None
+                            }
+                        }
+                    }
+                    Err(error) => {
+                        warn!("Sending the request failed: {}", error);
+                        None
+                    }
+                };
+                Ok::<_, ExecutionError>(Sample {
+                    latency: sent.elapsed(),
+                    status,
+                })
+            }
+        })
+        .buffer_unordered(concurrency)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<_, _>>()?;
+    let elapsed = started.elapsed();
+    debug!("Sent {} requests in {:?}", samples.len(), elapsed);
+
+    Report::new(&request.name, concurrency, elapsed, samples).render(raw);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(latency_ms: u64, status: Option<u16>) -> Sample {
+        Sample {
+            latency: Duration::from_millis(latency_ms),
+            status: status.map(|status| StatusCode::from_u16(status).unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_report_statistics() {
+        let mut samples: Vec<Sample> = (1..=98).map(|ms| sample(ms, Some(200))).collect();
+        samples.push(sample(500, Some(503)));
+        samples.push(sample(1000, None));
+
+        let report = Report::new("Get User", 4, Duration::from_secs(2), samples);
+
+        assert_eq!(report.throughput(), 50.0);
+        assert_eq!(report.errors(), 2);
+        assert_eq!(report.error_rate(), 0.02);
+        assert_eq!(report.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(report.percentile(90.0), Duration::from_millis(90));
+        assert_eq!(report.percentile(99.0), Duration::from_millis(500));
+        assert_eq!(
+            report.statuses(),
+            BTreeMap::from([
+                (None, 1),
+                (Some(StatusCode::OK), 98),
+                (Some(StatusCode::SERVICE_UNAVAILABLE), 1),
+            ])
+        );
+    }
+}
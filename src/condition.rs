@@ -0,0 +1,687 @@
+use crate::response::Response;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use thiserror::Error;
+use tracing::debug;
+
+#[derive(Debug, Error)]
+pub enum ConditionError {
+    #[error("Invalid condition `{expression}` at position {position}: {message}")]
+    Syntax {
+        expression: String,
+        position: usize,
+        message: String,
+    },
+    #[error("Unknown function `{function}` (expected status, header or json)")]
+    UnknownFunction { function: String },
+    #[error("`{function}` expects {expected}")]
+    InvalidArguments {
+        function: String,
+        expected: &'static str,
+    },
+    #[error("No response available for {}", .request.as_deref().map_or("the current request".to_string(), |r| format!("`{:?}`", r)))]
+    NoResponse { request: Option<String> },
+    #[error("Placeholder `{placeholder}` has no value")]
+    NoValue { placeholder: String },
+    #[error("Invalid regex `{pattern}`: {message}")]
+    InvalidRegex { pattern: String, message: String },
+}
+
+/// A boolean expression over placeholders and responses, such as
+/// `status("Get Profile") == 401 && {env} != "prod"`.
+///
+/// Responses are read with `status()`, `header(name)` and `json(pointer)`,
+/// each optionally preceded by the name of the request to read, e.g.
+/// `json("Create Job", "/id")`. Without a request name they read the
+/// response being checked (for polling).
+#[derive(Clone)]
+pub struct Condition {
+    source: String,
+    expression: Expr,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Literal(Value),
+    Placeholder(String),
+    Call {
+        function: Function,
+        request: Option<String>,
+        argument: Option<String>,
+    },
+    Not(Box<Expr>),
+    Binary {
+        operator: Operator,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Function {
+    Status,
+    Header,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    Matches,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    String(String),
+    Number(f64),
+    Ident(String),
+    Placeholder(String),
+    Operator(Operator),
+    Not,
+    LeftParen,
+    RightParen,
+    Comma,
+}
+
+/// Everything a condition can read when it's evaluated
+pub struct Context<'a> {
+    /// Resolved values of the condition's placeholders
+    pub variables: &'a HashMap<String, String>,
+    /// Latest responses of the requests the condition refers to
+    pub responses: &'a HashMap<String, Response>,
+    /// The response being checked, if any
+    pub current: Option<&'a Response>,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, ConditionError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            source,
+            tokens,
+            position: 0,
+        };
+        let expression = parser.parse_or()?;
+
+        if let Some((position, token)) = parser.tokens.get(parser.position) {
+            return Err(parser.error(*position, format!("unexpected {:?}", token)));
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            expression,
+        })
+    }
+
+    /// Names of the placeholders used in the condition
+    pub fn placeholders(&self) -> Vec<&str> {
+        let mut placeholders = Vec::new();
+        self.expression.visit(&mut |expr| {
+            if let Expr::Placeholder(name) = expr {
+                placeholders.push(name.as_str());
+            }
+        });
+        placeholders
+    }
+
+    /// Names of the requests whose responses the condition reads
+    pub fn requests(&self) -> Vec<&str> {
+        let mut requests = Vec::new();
+        self.expression.visit(&mut |expr| {
+            if let Expr::Call {
+                request: Some(request),
+                ..
+            } = expr
+            {
+                requests.push(request.as_str());
+            }
+        });
+        requests
+    }
+
+    pub fn evaluate(&self, context: &Context) -> Result<bool, ConditionError> {
+        let value = self.expression.evaluate(context)?;
+        debug!(condition = %self.source, %value, "Evaluated condition");
+        Ok(truthy(&value))
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl fmt::Debug for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Condition({:?})", self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Condition {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let source = String::deserialize(deserializer)?;
+        Condition::parse(&source).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Expr {
+    fn visit<'a>(&'a self, visitor: &mut impl FnMut(&'a Expr)) {
+        visitor(self);
+        match self {
+            Expr::Not(inner) => inner.visit(visitor),
+            Expr::Binary { left, right, .. } => {
+                left.visit(visitor);
+                right.visit(visitor);
+            }
+            _ => {}
+        }
+    }
+
+    fn evaluate(&self, context: &Context) -> Result<Value, ConditionError> {
+        match self {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Placeholder(name) => context
+                .variables
+                .get(name)
+                .map(|value| Value::String(value.clone()))
+                .ok_or_else(|| ConditionError::NoValue {
+                    placeholder: name.clone(),
+                }),
+            Expr::Call {
+                function,
+                request,
+                argument,
+            } => {
+                let response = match request {
+                    Some(request) => context.responses.get(request),
+                    None => context.current,
+                }
+                .ok_or_else(|| ConditionError::NoResponse {
+                    request: request.clone(),
+                })?;
+
+                Ok(match (function, argument) {
+                    (Function::Status, _) => Value::from(response.status.as_u16()),
+                    (Function::Header, Some(name)) => response
+                        .headers
+                        .get(name)
+                        .and_then(|value| value.to_str().ok())
+                        .map_or(Value::Null, |value| Value::String(value.to_string())),
+                    (Function::Json, Some(pointer)) => response
+                        .json()
+                        .ok()
+                        .and_then(|json| json.pointer(pointer).cloned())
+                        .unwrap_or(Value::Null),
+                    (_, None) => Value::Null,
+                })
+            }
+            Expr::Not(inner) => Ok(Value::Bool(!truthy(&inner.evaluate(context)?))),
+            Expr::Binary {
+                operator: Operator::And,
+                left,
+                right,
+            } => Ok(Value::Bool(
+                truthy(&left.evaluate(context)?) && truthy(&right.evaluate(context)?),
+            )),
+            Expr::Binary {
+                operator: Operator::Or,
+                left,
+                right,
+            } => Ok(Value::Bool(
+                truthy(&left.evaluate(context)?) || truthy(&right.evaluate(context)?),
+            )),
+            Expr::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                let left = left.evaluate(context)?;
+                let right = right.evaluate(context)?;
+                compare(*operator, &left, &right).map(Value::Bool)
+            }
+        }
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(boolean) => *boolean,
+        Value::Number(number) => number.as_f64() != Some(0.0),
+        Value::String(string) => !string.is_empty(),
+        Value::Array(_) | Value::Object(_) => true,
+    }
+}
+
+/// Numbers compare with strings that parse as numbers, since placeholders
+/// always resolve to strings
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(string) => string.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn as_text(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Numbers are equal by value, whether they're integers or floats, and to
+/// strings that parse as the same number
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left, right, as_number(left).zip(as_number(right))) {
+        (Value::Number(_), _, Some((l, r))) | (_, Value::Number(_), Some((l, r))) => l == r,
+        _ => left == right,
+    }
+}
+
+fn compare(operator: Operator, left: &Value, right: &Value) -> Result<bool, ConditionError> {
+    let numbers = as_number(left).zip(as_number(right));
+
+    Ok(match operator {
+        Operator::Eq | Operator::Ne => equal(left, right) == (operator == Operator::Eq),
+        Operator::Lt | Operator::Le | Operator::Gt | Operator::Ge => {
+            let ordering = match numbers {
+                Some((l, r)) => l.partial_cmp(&r),
+                None => Some(as_text(left).cmp(&as_text(right))),
+            };
+            match (operator, ordering) {
+                (_, None) => false,
+                (Operator::Lt, Some(ordering)) => ordering.is_lt(),
+                (Operator::Le, Some(ordering)) => ordering.is_le(),
+                (Operator::Gt, Some(ordering)) => ordering.is_gt(),
+                (_, Some(ordering)) => ordering.is_ge(),
+            }
+        }
+        Operator::Contains => match left {
+            Value::Array(items) => items.iter().any(|item| equal(item, right)),
+            Value::Object(map) => map.contains_key(&as_text(right)),
+            other => as_text(other).contains(&as_text(right)),
+        },
+        Operator::Matches => {
+            let pattern = as_text(right);
+            let regex = Regex::new(&pattern).map_err(|error| ConditionError::InvalidRegex {
+                pattern: pattern.clone(),
+                message: error.to_string(),
+            })?;
+            regex.is_match(&as_text(left))
+        }
+        Operator::And | Operator::Or => unreachable!("logical operators are evaluated lazily"),
+    })
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ConditionError> {
+    let syntax_error = |position: usize, message: &str| ConditionError::Syntax {
+        expression: source.to_string(),
+        position,
+        message: message.to_string(),
+    };
+    let chars: Vec<(usize, char)> = source.char_indices().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let (position, c) = chars[index];
+        let next = chars.get(index + 1).map(|(_, c)| *c);
+
+        let (token, width) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                index += 1;
+                continue;
+            }
+            ('(', _) => (Token::LeftParen, 1),
+            (')', _) => (Token::RightParen, 1),
+            (',', _) => (Token::Comma, 1),
+            ('=', Some('=')) => (Token::Operator(Operator::Eq), 2),
+            ('!', Some('=')) => (Token::Operator(Operator::Ne), 2),
+            ('<', Some('=')) => (Token::Operator(Operator::Le), 2),
+            ('>', Some('=')) => (Token::Operator(Operator::Ge), 2),
+            ('&', Some('&')) => (Token::Operator(Operator::And), 2),
+            ('|', Some('|')) => (Token::Operator(Operator::Or), 2),
+            ('<', _) => (Token::Operator(Operator::Lt), 1),
+            ('>', _) => (Token::Operator(Operator::Gt), 1),
+            ('!', _) => (Token::Not, 1),
+            ('"' | '\'', _) => {
+                let end = chars[index + 1..]
+                    .iter()
+                    .position(|(_, candidate)| *candidate == c)
+                    .ok_or_else(|| syntax_error(position, "unterminated string"))?;
+                let string: String = chars[index + 1..index + 1 + end]
+                    .iter()
+                    .map(|(_, c)| c)
+                    .collect();
+                (Token::String(string), end + 2)
+            }
+            ('{', _) => {
+                let end = chars[index + 1..]
+                    .iter()
+                    .position(|(_, candidate)| *candidate == '}')
+                    .ok_or_else(|| syntax_error(position, "unterminated placeholder"))?;
+                let name: String = chars[index + 1..index + 1 + end]
+                    .iter()
+                    .map(|(_, c)| c)
+                    .collect();
+                (Token::Placeholder(name.trim().to_string()), end + 2)
+            }
+            (c, _) if c.is_ascii_digit() || c == '-' => {
+                let width = chars[index + 1..]
+                    .iter()
+                    .take_while(|(_, c)| c.is_ascii_digit() || *c == '.')
+                    .count()
+                    + 1;
+                let text: String = chars[index..index + width].iter().map(|(_, c)| c).collect();
+                let number = text
+                    .parse()
+                    .map_err(|_| syntax_error(position, "invalid number"))?;
+                (Token::Number(number), width)
+            }
+            (c, _) if c.is_alphabetic() || c == '_' => {
+                let width = chars[index..]
+                    .iter()
+                    .take_while(|(_, c)| c.is_alphanumeric() || *c == '_')
+                    .count();
+                let word: String = chars[index..index + width].iter().map(|(_, c)| c).collect();
+                let token = match word.as_str() {
+                    "contains" => Token::Operator(Operator::Contains),
+                    "matches" => Token::Operator(Operator::Matches),
+                    _ => Token::Ident(word),
+                };
+                (token, width)
+            }
+            _ => return Err(syntax_error(position, &format!("unexpected `{}`", c))),
+        };
+
+        tokens.push((position, token));
+        index += width;
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, position: usize, message: String) -> ConditionError {
+        ConditionError::Syntax {
+            expression: self.source.to_string(),
+            position,
+            message,
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> Result<(usize, Token), ConditionError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| self.error(self.source.len(), "unexpected end".to_string()))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ConditionError> {
+        let (position, token) = self.next()?;
+        if token != expected {
+            return Err(self.error(
+                position,
+                format!("expected {:?}, found {:?}", expected, token),
+            ));
+        }
+        Ok(())
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ConditionError> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Operator(Operator::Or)) {
+            self.position += 1;
+            let right = self.parse_and()?;
+            left = Expr::Binary {
+                operator: Operator::Or,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ConditionError> {
+        let mut left = self.parse_unary()?;
+        while self.peek() == Some(&Token::Operator(Operator::And)) {
+            self.position += 1;
+            let right = self.parse_unary()?;
+            left = Expr::Binary {
+                operator: Operator::And,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ConditionError> {
+        if self.peek() == Some(&Token::Not) {
+            self.position += 1;
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ConditionError> {
+        let left = self.parse_operand()?;
+        match self.peek() {
+            Some(Token::Operator(operator))
+                if !matches!(operator, Operator::And | Operator::Or) =>
+            {
+                let operator = *operator;
+                self.position += 1;
+                let right = self.parse_operand()?;
+                Ok(Expr::Binary {
+                    operator,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                })
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn parse_operand(&mut self) -> Result<Expr, ConditionError> {
+        let (position, token) = self.next()?;
+        match token {
+            Token::String(string) => Ok(Expr::Literal(Value::String(string))),
+            Token::Number(number) => Ok(Expr::Literal(Value::from(number))),
+            Token::Placeholder(name) => Ok(Expr::Placeholder(name)),
+            Token::LeftParen => {
+                let inner = self.parse_or()?;
+                self.expect(Token::RightParen)?;
+                Ok(inner)
+            }
+            Token::Ident(word) => match word.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                _ => self.parse_call(word),
+            },
+            other => Err(self.error(position, format!("unexpected {:?}", other))),
+        }
+    }
+
+    fn parse_call(&mut self, name: String) -> Result<Expr, ConditionError> {
+        let function = match name.as_str() {
+            "status" => Function::Status,
+            "header" => Function::Header,
+            "json" => Function::Json,
+            _ => return Err(ConditionError::UnknownFunction { function: name }),
+        };
+
+        self.expect(Token::LeftParen)?;
+        let mut arguments = Vec::new();
+        while self.peek() != Some(&Token::RightParen) {
+            if !arguments.is_empty() {
+                self.expect(Token::Comma)?;
+            }
+            match self.next()? {
+                (_, Token::String(argument)) => arguments.push(argument),
+                (position, other) => {
+                    return Err(
+                        self.error(position, format!("expected a string, found {:?}", other))
+                    )
+                }
+            }
+        }
+        self.expect(Token::RightParen)?;
+
+        let (request, argument) = match (function, arguments.len()) {
+            (Function::Status, 0) => (None, None),
+            (Function::Status, 1) => (arguments.pop(), None),
+            (Function::Header | Function::Json, 1) => (None, arguments.pop()),
+            (Function::Header | Function::Json, 2) => {
+                let argument = arguments.pop();
+                (arguments.pop(), argument)
+            }
+            (Function::Status, _) => {
+                return Err(ConditionError::InvalidArguments {
+                    function: name,
+                    expected: "an optional request name",
+                })
+            }
+            _ => {
+                return Err(ConditionError::InvalidArguments {
+                    function: name,
+                    expected: "an optional request name and one argument",
+                })
+            }
+        };
+
+        Ok(Expr::Call {
+            function,
+            request,
+            argument,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderMap;
+
+    fn response(status: u16, body: &str) -> Response {
+        Response::fixture(
+            r#"
+            name = "Get Profile"
+            method = "GET"
+            url = "https://example.com/me"
+            "#,
+            status,
+            HeaderMap::new(),
+            body,
+        )
+    }
+
+    #[test]
+    fn test_condition_reads_responses_and_placeholders() {
+        let condition =
+            Condition::parse(r#"status("Get Profile") == 401 && !({env} == 'prod')"#).unwrap();
+        assert_eq!(condition.requests(), ["Get Profile"]);
+        assert_eq!(condition.placeholders(), ["env"]);
+
+        let variables = HashMap::from([("env".to_string(), "dev".to_string())]);
+        let responses = HashMap::from([("Get Profile".to_string(), response(401, "{}"))]);
+        let context = Context {
+            variables: &variables,
+            responses: &responses,
+            current: None,
+        };
+
+        assert!(condition.evaluate(&context).unwrap());
+    }
+
+    #[test]
+    fn test_condition_reads_the_current_response() {
+        let condition =
+            Condition::parse(r#"json("/status") == "completed" || json("/progress") >= 100"#)
+                .unwrap();
+        let variables = HashMap::new();
+        let responses = HashMap::new();
+        let check = |body: &str| {
+            let current = response(200, body);
+            condition
+                .evaluate(&Context {
+                    variables: &variables,
+                    responses: &responses,
+                    current: Some(&current),
+                })
+                .unwrap()
+        };
+
+        assert!(check(r#"{"status": "completed"}"#));
+        assert!(check(r#"{"status": "running", "progress": 100}"#));
+        assert!(!check(r#"{"status": "running", "progress": 40}"#));
+    }
+
+    #[test]
+    fn test_contains_compares_array_numbers_by_value() {
+        let variables = HashMap::new();
+        let responses = HashMap::new();
+        let current = response(200, r#"{"ids": [1, 2], "tags": ["beta"]}"#);
+        let check = |expression: &str| {
+            Condition::parse(expression)
+                .unwrap()
+                .evaluate(&Context {
+                    variables: &variables,
+                    responses: &responses,
+                    current: Some(&current),
+                })
+                .unwrap()
+        };
+
+        assert!(check(r#"json("/ids") contains 2"#));
+        assert!(check(r#"json("/ids") contains 2.0"#));
+        assert!(!check(r#"json("/ids") contains 3"#));
+        assert!(check(r#"json("/tags") contains "beta""#));
+    }
+
+    #[test]
+    fn test_condition_syntax_errors_point_at_the_problem() {
+        match Condition::parse(r#"status() == "#) {
+            Err(ConditionError::Syntax { position, .. }) => assert_eq!(position, 12),
+            other => panic!(
+                "expected a syntax error, got {:?}",
+                other.map(|c| c.to_string())
+            ),
+        }
+        assert!(matches!(
+            Condition::parse("body() == 1"),
+            Err(ConditionError::UnknownFunction { .. })
+        ));
+    }
+}
@@ -0,0 +1,877 @@
+use crate::private_file;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use thiserror::Error;
+use toml_edit::{DocumentMut, InlineTable, Item, Table, TableLike};
+use tracing::info;
+
+#[derive(Debug, Error)]
+pub enum EnvFileError {
+    #[error("Failed to read env file `{path}`: {source}")]
+    Unreadable {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Invalid {format} env file `{path}`: {message}")]
+    Invalid {
+        path: String,
+        format: EnvFileFormat,
+        message: String,
+    },
+    #[error("Failed to save env file `{path}`: {source}")]
+    SaveFailed {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Can't set `{key}` in env file `{path}`: `{parent}` isn't a table")]
+    NotATable {
+        path: String,
+        key: String,
+        parent: String,
+    },
+}
+
+/// How an env file is written
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+pub enum EnvFileFormat {
+    /// `KEY=value` lines, as read by most `.env` loaders
+    Dotenv,
+    Json,
+    Toml,
+}
+
+impl EnvFileFormat {
+    /// Guess the format from the file name: `.env`, `app.env` and
+    /// `.env.local` are dotenv, `*.json` is JSON and anything else is TOML
+    pub fn detect(path: &str) -> Self {
+        let name = Path::new(path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(path);
+
+        if name == ".env" || name.starts_with(".env.") || name.ends_with(".env") {
+            Self::Dotenv
+        } else if name.ends_with(".json") {
+            Self::Json
+        } else {
+            Self::Toml
+        }
+    }
+}
+
+impl fmt::Display for EnvFileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dotenv => write!(f, "dotenv"),
+            Self::Json => write!(f, "JSON"),
+            Self::Toml => write!(f, "TOML"),
+        }
+    }
+}
+
+/// A loaded env file's values
+type EnvData = Map<String, Value>;
+
+// Cache for loaded env files, by path and the format they were read as
+static ENV_FILES_CACHE: Lazy<Mutex<HashMap<(String, EnvFileFormat), EnvData>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Load an env file, in the given format or the one its name suggests.
+/// TOML and JSON files keep their types and nesting; dotenv values are
+/// always strings. Values in the file's local copy (see [`local_path`])
+/// take precedence, if it exists.
+pub fn load(path: &str, format: Option<EnvFileFormat>) -> Result<Map<String, Value>, EnvFileError> {
+    let format = format.unwrap_or_else(|| EnvFileFormat::detect(path));
+    let key = (path.to_string(), format);
+    if let Some(data) = ENV_FILES_CACHE.lock().unwrap().get(&key) {
+        return Ok(data.clone());
+    }
+
+    // Read without holding the lock, so other files can load meanwhile
+    let mut data = read(path, format)?;
+    merge_local(path, format, &mut data)?;
+
+    ENV_FILES_CACHE.lock().unwrap().insert(key, data.clone());
+    Ok(data)
+}
+
+/// Like [`load`], but a missing file counts as empty, so that values can
+/// live only in its local copy until the shared file is created
+pub fn load_local(
+    path: &str,
+    format: Option<EnvFileFormat>,
+) -> Result<Map<String, Value>, EnvFileError> {
+    match load(path, format) {
+        Err(EnvFileError::Unreadable { source, .. })
+            if source.kind() == std::io::ErrorKind::NotFound =>
+        {
+            // Not cached, since the file may be created later
+            let format = format.unwrap_or_else(|| EnvFileFormat::detect(path));
+            let mut data = Map::new();
+            merge_local(path, format, &mut data)?;
+            Ok(data)
+        }
+        result => result,
+    }
+}
+
+fn merge_local(
+    path: &str,
+    format: EnvFileFormat,
+    data: &mut Map<String, Value>,
+) -> Result<(), EnvFileError> {
+    let local = local_path(path);
+    if Path::new(&local).exists() {
+        merge(data, read(&local, format)?);
+    }
+    Ok(())
+}
+
+fn read(path: &str, format: EnvFileFormat) -> Result<Map<String, Value>, EnvFileError> {
+    let content = fs::read_to_string(path).map_err(|source| EnvFileError::Unreadable {
+        path: path.to_string(),
+        source,
+    })?;
+    parse(&content, format).map_err(|message| EnvFileError::Invalid {
+        path: path.to_string(),
+        format,
+        message,
+    })
+}
+
+/// Merge `overrides` into `data`, table by table
+fn merge(data: &mut Map<String, Value>, overrides: Map<String, Value>) {
+    for (key, value) in overrides {
+        match (data.get_mut(&key), value) {
+            (Some(Value::Object(table)), Value::Object(value)) => merge(table, value),
+            (_, value) => {
+                data.insert(key, value);
+            }
+        }
+    }
+}
+
+/// The local copy of an env file, for values that shouldn't be shared:
+/// `secrets.toml` has `secrets.local.toml` and `.env` has `.env.local`
+pub fn local_path(path: &str) -> String {
+    let path = Path::new(path);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let local_name = match name.rsplit_once('.') {
+        _ if name == ".env" || name.starts_with(".env.") => format!("{}.local", name),
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{}.local.{}", stem, extension)
+        }
+        _ => format!("{}.local", name),
+    };
+    path.with_file_name(local_name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Look a key up in a loaded env file. A key that isn't in the file as is
+/// is read as a dotted path, so `services.auth.port` finds `port` in the
+/// `[services.auth]` table, and numbers index into arrays.
+pub fn lookup<'a>(data: &'a Map<String, Value>, key: &str) -> Option<&'a Value> {
+    if let Some(value) = data.get(key) {
+        return Some(value);
+    }
+
+    let mut segments = key.split('.');
+    let mut value = data.get(segments.next()?)?;
+    for segment in segments {
+        value = match value {
+            Value::Object(map) => map.get(segment)?,
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// Add a value to an env file and save it, or to its local copy (which is
+/// created if needed) when `local` is set. Only the new value is added, so
+/// comments and layout are kept, and a dotted key is set in nested TOML
+/// and JSON tables, which are created as needed. The file is replaced in
+/// one step and is only readable by its owner, since it may now hold a
+/// secret.
+pub fn insert(
+    path: &str,
+    format: Option<EnvFileFormat>,
+    key: &str,
+    value: &str,
+    local: bool,
+) -> Result<(), EnvFileError> {
+    let format = format.unwrap_or_else(|| EnvFileFormat::detect(path));
+    let target = if local {
+        local_path(path)
+    } else {
+        path.to_string()
+    };
+
+    let content = match fs::read_to_string(&target) {
+        Ok(content) => content,
+        Err(error) if local && error.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(source) => {
+            return Err(EnvFileError::Unreadable {
+                path: target,
+                source,
+            })
+        }
+    };
+    let content = match format {
+        EnvFileFormat::Dotenv => append_dotenv(content, key, value),
+        EnvFileFormat::Json => insert_json(&content, key, value),
+        EnvFileFormat::Toml => insert_toml(&content, key, value),
+    }
+    .map_err(|error| match error {
+        InsertError::Invalid(message) => EnvFileError::Invalid {
+            path: target.clone(),
+            format,
+            message,
+        },
+        InsertError::NotATable(parent) => EnvFileError::NotATable {
+            path: target.clone(),
+            key: key.to_string(),
+            parent,
+        },
+    })?;
+
+    private_file::write(Path::new(&target), content.as_bytes()).map_err(|source| {
+        EnvFileError::SaveFailed {
+            path: target.clone(),
+            source,
+        }
+    })?;
+    info!("Saved `{}` to {}", key, target);
+
+    // Load the file again next time it's needed
+    ENV_FILES_CACHE
+        .lock()
+        .unwrap()
+        .retain(|(cached, _), _| cached != path);
+    Ok(())
+}
+
+enum InsertError {
+    Invalid(String),
+    /// The path of a value a dotted key tried to walk into
+    NotATable(String),
+}
+
+fn append_dotenv(mut content: String, key: &str, value: &str) -> Result<String, InsertError> {
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&format!("{}={}\n", key, quote_dotenv(value)));
+    Ok(content)
+}
+
+fn insert_json(content: &str, key: &str, value: &str) -> Result<String, InsertError> {
+    let mut data = if content.trim().is_empty() {
+        Map::new()
+    } else {
+        serde_json::from_str(content).map_err(|error| InsertError::Invalid(error.to_string()))?
+    };
+    insert_path(&mut data, key, value).map_err(InsertError::NotATable)?;
+    Ok(serde_json::to_string_pretty(&data).expect("JSON values are serializable") + "\n")
+}
+
+/// Add a value to a TOML document without touching the rest of it
+fn insert_toml(content: &str, key: &str, value: &str) -> Result<String, InsertError> {
+    let mut document: DocumentMut = content
+        .parse()
+        .map_err(|error: toml_edit::TomlError| InsertError::Invalid(error.to_string()))?;
+    let (parents, name) = split_key(document.contains_key(key), key);
+
+    let mut table: &mut dyn TableLike = document.as_table_mut();
+    let mut inline = false;
+    let mut path = String::new();
+    for segment in parents {
+        if !path.is_empty() {
+            path.push('.');
+        }
+        path.push_str(segment);
+
+        // Tables inside inline tables have to be inline too
+        let new_table = if inline {
+            Item::Value(toml_edit::Value::InlineTable(InlineTable::new()))
+        } else {
+            let mut new_table = Table::new();
+            new_table.set_implicit(true);
+            Item::Table(new_table)
+        };
+        let item = table.entry(segment).or_insert(new_table);
+        inline = item.is_inline_table();
+        table = item
+            .as_table_like_mut()
+            .ok_or_else(|| InsertError::NotATable(path.clone()))?;
+    }
+
+    table.insert(name, toml_edit::value(value));
+    if inline {
+        // Otherwise the space before the closing brace ends up before the
+        // new value's comma
+        table.fmt();
+    }
+    Ok(document.to_string())
+}
+
+/// Split a dotted key into the tables it walks through and its own name. A
+/// key that is already in the file as is isn't split.
+fn split_key(exists: bool, key: &str) -> (Vec<&str>, &str) {
+    match key.rsplit_once('.') {
+        Some((parents, name)) if !exists => (parents.split('.').collect(), name),
+        _ => (Vec::new(), key),
+    }
+}
+
+/// Set `key` in the nested table its dotted path leads to. Returns the path
+/// of the first value along the way that isn't a table.
+fn insert_path(data: &mut Map<String, Value>, key: &str, value: &str) -> Result<(), String> {
+    let (parents, name) = split_key(data.contains_key(key), key);
+
+    let mut table = data;
+    let mut path = String::new();
+    for segment in parents {
+        if !path.is_empty() {
+            path.push('.');
+        }
+        path.push_str(segment);
+        table = match table
+            .entry(segment)
+            .or_insert_with(|| Value::Object(Map::new()))
+        {
+            Value::Object(map) => map,
+            _ => return Err(path),
+        };
+    }
+
+    table.insert(name.to_string(), Value::String(value.to_string()));
+    Ok(())
+}
+
+fn parse(content: &str, format: EnvFileFormat) -> Result<Map<String, Value>, String> {
+    match format {
+        EnvFileFormat::Dotenv => Ok(parse_dotenv(content)?
+            .into_iter()
+            .map(|(key, value)| (key, Value::String(value)))
+            .collect()),
+        EnvFileFormat::Json => serde_json::from_str(content).map_err(|error| error.to_string()),
+        EnvFileFormat::Toml => {
+            let table: toml::Table = toml::from_str(content).map_err(|error| error.to_string())?;
+            Ok(table
+                .into_iter()
+                .map(|(key, value)| (key, toml_to_json(value)))
+                .collect())
+        }
+    }
+}
+
+/// Convert a TOML value to JSON. Dates and times become strings.
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(string) => Value::String(string),
+        toml::Value::Integer(integer) => Value::from(integer),
+        toml::Value::Float(float) => Value::from(float),
+        toml::Value::Boolean(boolean) => Value::Bool(boolean),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(items) => Value::Array(items.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Parse a dotenv file. Lines are `KEY=value`, optionally starting with
+/// `export`. Values can be single-quoted (taken literally), double-quoted
+/// (with escapes like `\n`) or unquoted (where ` #` starts a comment), and
+/// quoted values can span lines. `${VAR}`, `${VAR:-default}` and `$VAR` are
+/// expanded in double-quoted and unquoted values, from keys defined earlier
+/// in the file and then from the environment.
+fn parse_dotenv(content: &str) -> Result<HashMap<String, String>, String> {
+    let mut parser = DotenvParser {
+        chars: content.chars().collect(),
+        position: 0,
+        line: 1,
+        values: HashMap::new(),
+    };
+    parser.parse()?;
+    Ok(parser.values)
+}
+
+struct DotenvParser {
+    chars: Vec<char>,
+    position: usize,
+    line: usize,
+    values: HashMap<String, String>,
+}
+
+impl DotenvParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("line {}: {}", self.line, message)
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.next();
+        }
+    }
+
+    /// Skip the rest of the line, which must be blank or a comment
+    fn finish_line(&mut self) -> Result<(), String> {
+        self.skip_spaces();
+        match self.peek() {
+            None | Some('\n') => {}
+            Some('\r') if self.chars.get(self.position + 1) == Some(&'\n') => {}
+            Some('#') => {
+                while !matches!(self.peek(), None | Some('\n')) {
+                    self.next();
+                }
+            }
+            Some(c) => return Err(self.error(&format!("unexpected `{}` after value", c))),
+        }
+        self.next();
+        Ok(())
+    }
+
+    fn parse(&mut self) -> Result<(), String> {
+        loop {
+            while matches!(self.peek(), Some(' ' | '\t' | '\r' | '\n')) {
+                self.next();
+            }
+            match self.peek() {
+                None => return Ok(()),
+                Some('#') => {
+                    self.finish_line()?;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let mut key = self.take_key();
+            if key == "export" && matches!(self.peek(), Some(' ' | '\t')) {
+                self.skip_spaces();
+                key = self.take_key();
+            }
+            if key.is_empty() {
+                return Err(self.error("expected a key"));
+            }
+
+            self.skip_spaces();
+            if self.next() != Some('=') {
+                return Err(self.error(&format!("expected `=` after `{}`", key)));
+            }
+            self.skip_spaces();
+
+            let value = match self.peek() {
+                Some('\'') => {
+                    self.next();
+                    let value = self.take_quoted('\'')?;
+                    self.finish_line()?;
+                    value
+                }
+                Some('"') => {
+                    self.next();
+                    let value = self.take_quoted('"')?;
+                    self.finish_line()?;
+                    self.expand(&unescape(&value))?
+                }
+                _ => {
+                    let value = self.take_unquoted();
+                    self.expand(&value)?
+                }
+            };
+            self.values.insert(key, value);
+        }
+    }
+
+    fn take_key(&mut self) -> String {
+        let mut key = String::new();
+        while let Some(c) = self
+            .peek()
+            .filter(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        {
+            key.push(c);
+            self.next();
+        }
+        key
+    }
+
+    /// Everything up to the closing quote, with escaped quotes kept escaped
+    fn take_quoted(&mut self, quote: char) -> Result<String, String> {
+        let line = self.line;
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                None => return Err(format!("line {}: unclosed {} quote", line, quote)),
+                Some(c) if c == quote => return Ok(value),
+                Some('\\') if quote == '"' => {
+                    value.push('\\');
+                    value.extend(self.next());
+                }
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    /// The rest of the line, without a trailing ` # comment`
+    fn take_unquoted(&mut self) -> String {
+        let mut value = String::new();
+        while let Some(c) = self.peek().filter(|c| *c != '\n') {
+            if c == '#' && (value.is_empty() || value.ends_with([' ', '\t'])) {
+                while !matches!(self.peek(), None | Some('\n')) {
+                    self.next();
+                }
+                break;
+            }
+            value.push(c);
+            self.next();
+        }
+        value.trim().to_string()
+    }
+
+    /// Expand `${VAR}`, `${VAR:-default}` and `$VAR`. A `\$` in a
+    /// double-quoted value arrives here as `ESCAPED_DOLLAR` and stays a `$`.
+    fn expand(&self, value: &str) -> Result<String, String> {
+        let chars: Vec<char> = value.chars().collect();
+        let mut expanded = String::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                ESCAPED_DOLLAR => {
+                    expanded.push('$');
+                    i += 1;
+                }
+                '$' if chars.get(i + 1) == Some(&'{') => {
+                    let end = chars[i..]
+                        .iter()
+                        .position(|c| *c == '}')
+                        .map(|offset| i + offset)
+                        .ok_or_else(|| self.error("unclosed `${`"))?;
+                    let expression: String = chars[i + 2..end].iter().collect();
+                    let (name, default) = match expression.split_once(":-") {
+                        Some((name, default)) => (name, Some(default)),
+                        None => (expression.as_str(), None),
+                    };
+                    match (self.lookup(name), default) {
+                        (Some(value), _) if !value.is_empty() => expanded.push_str(&value),
+                        (_, Some(default)) => expanded.push_str(default),
+                        (value, None) => expanded.push_str(&value.unwrap_or_default()),
+                    }
+                    i = end + 1;
+                }
+                '$' if chars
+                    .get(i + 1)
+                    .is_some_and(|c| c.is_alphabetic() || *c == '_') =>
+                {
+                    let name: String = chars[i + 1..]
+                        .iter()
+                        .take_while(|c| c.is_alphanumeric() || **c == '_')
+                        .collect();
+                    expanded.push_str(&self.lookup(&name).unwrap_or_default());
+                    i += 1 + name.chars().count();
+                }
+                c => {
+                    expanded.push(c);
+                    i += 1;
+                }
+            }
+        }
+
+        Ok(expanded)
+    }
+
+    fn lookup(&self, name: &str) -> Option<String> {
+        self.values
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+    }
+}
+
+/// Stands in for `\$` between unescaping and expansion
+const ESCAPED_DOLLAR: char = '\u{0}';
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some('$') => unescaped.push(ESCAPED_DOLLAR),
+            Some(c @ ('"' | '\\')) => unescaped.push(c),
+            Some(c) => {
+                unescaped.push('\\');
+                unescaped.push(c);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Quote a value so `parse_dotenv` reads it back unchanged
+fn quote_dotenv(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '$' => quoted.push_str("\\$"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dotenv() {
+        std::env::set_var("GLINT_TEST_HOST", "example.com");
+        let values = parse_dotenv(
+            r#"
+# Comment
+export API_KEY=abc123
+EMPTY=
+UNQUOTED = some value # trailing comment
+HASH=a#b
+SINGLE='literal ${API_KEY} \n'
+DOUBLE="line\nbreak \"quoted\" \$5"
+URL=https://${GLINT_TEST_HOST}/$API_KEY
+FALLBACK=${MISSING_VAR:-fallback}
+MULTILINE="-----BEGIN KEY-----
+abc
+-----END KEY-----"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(values["API_KEY"], "abc123");
+        assert_eq!(values["EMPTY"], "");
+        assert_eq!(values["UNQUOTED"], "some value");
+        assert_eq!(values["HASH"], "a#b");
+        assert_eq!(values["SINGLE"], r"literal ${API_KEY} \n");
+        assert_eq!(values["DOUBLE"], "line\nbreak \"quoted\" $5");
+        assert_eq!(values["URL"], "https://example.com/abc123");
+        assert_eq!(values["FALLBACK"], "fallback");
+        assert_eq!(
+            values["MULTILINE"],
+            "-----BEGIN KEY-----\nabc\n-----END KEY-----"
+        );
+
+        assert_eq!(
+            parse_dotenv("A=1\nB='open").unwrap_err(),
+            "line 2: unclosed ' quote"
+        );
+        assert_eq!(
+            parse_dotenv("A=\"x\" y").unwrap_err(),
+            "line 1: unexpected `y` after value"
+        );
+
+        let value = "say \"hi\"\n$HOME \\o/";
+        assert_eq!(
+            parse_dotenv(&format!("V={}", quote_dotenv(value))).unwrap()["V"],
+            value
+        );
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(EnvFileFormat::detect(".env"), EnvFileFormat::Dotenv);
+        assert_eq!(
+            EnvFileFormat::detect("config/.env.local"),
+            EnvFileFormat::Dotenv
+        );
+        assert_eq!(EnvFileFormat::detect("prod.env"), EnvFileFormat::Dotenv);
+        assert_eq!(EnvFileFormat::detect("secrets.json"), EnvFileFormat::Json);
+        assert_eq!(EnvFileFormat::detect("prod.toml"), EnvFileFormat::Toml);
+
+        assert_eq!(
+            parse(
+                r#"{"port": 8080, "debug": true, "name": "api"}"#,
+                EnvFileFormat::Json
+            )
+            .unwrap(),
+            serde_json::json!({"port": 8080, "debug": true, "name": "api"})
+                .as_object()
+                .unwrap()
+                .clone()
+        );
+
+        // The same file read as two formats is cached once per format
+        let path = std::env::temp_dir().join(format!("glint-format-{}", std::process::id()));
+        fs::write(&path, "PORT=8080\n").unwrap();
+        let path = path.to_str().unwrap();
+        assert_eq!(
+            load(path, Some(EnvFileFormat::Toml)).unwrap()["PORT"],
+            Value::from(8080)
+        );
+        assert_eq!(
+            load(path, Some(EnvFileFormat::Dotenv)).unwrap()["PORT"],
+            "8080"
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_nested_values() {
+        let mut data = parse(
+            r#"
+port = 8080
+debug = false
+started = 2024-05-31T12:00:00Z
+"dotted.key" = "as is"
+
+[services.auth]
+url = "https://auth.example.com"
+scopes = ["read", "write"]
+"#,
+            EnvFileFormat::Toml,
+        )
+        .unwrap();
+
+        assert_eq!(lookup(&data, "port"), Some(&Value::from(8080)));
+        assert_eq!(lookup(&data, "debug"), Some(&Value::Bool(false)));
+        assert_eq!(lookup(&data, "started").unwrap(), "2024-05-31T12:00:00Z");
+        assert_eq!(lookup(&data, "dotted.key").unwrap(), "as is");
+        assert_eq!(
+            lookup(&data, "services.auth.url").unwrap(),
+            "https://auth.example.com"
+        );
+        assert_eq!(lookup(&data, "services.auth.scopes.1").unwrap(), "write");
+        assert_eq!(lookup(&data, "services.auth.missing"), None);
+        assert_eq!(lookup(&data, "port.number"), None);
+
+        insert_path(&mut data, "services.billing.token", "abc").unwrap();
+        assert_eq!(lookup(&data, "services.billing.token").unwrap(), "abc");
+        insert_path(&mut data, "dotted.key", "changed").unwrap();
+        assert_eq!(data["dotted.key"], "changed");
+        assert_eq!(
+            insert_path(&mut data, "services.auth.url.path", "x").unwrap_err(),
+            "services.auth.url"
+        );
+
+        // Nested tables are saved as tables
+        let saved = toml::to_string(&data).unwrap();
+        assert_eq!(parse(&saved, EnvFileFormat::Toml).unwrap(), data);
+    }
+
+    #[test]
+    fn test_insert_keeps_layout() {
+        let content = r#"# Shared settings
+port = 8080 # the API port
+
+[services.auth]
+url = "https://auth.example.com"
+limits = { rate = 10 }
+"#;
+
+        let Ok(saved) = insert_toml(content, "services.auth.token", "abc") else {
+            panic!("inserting failed");
+        };
+        assert_eq!(
+            saved,
+            r#"# Shared settings
+port = 8080 # the API port
+
+[services.auth]
+url = "https://auth.example.com"
+limits = { rate = 10 }
+token = "abc"
+"#
+        );
+        let Ok(saved) = insert_toml(&saved, "services.auth.limits.burst", "5") else {
+            panic!("inserting failed");
+        };
+        assert!(saved.contains(r#"limits = { rate = 10, burst = "5" }"#));
+        let Ok(saved) = insert_toml(&saved, "billing.key", "k") else {
+            panic!("inserting failed");
+        };
+        assert!(saved.ends_with("[billing]\nkey = \"k\"\n"));
+        assert!(matches!(
+            insert_toml(content, "port.number", "1"),
+            Err(InsertError::NotATable(parent)) if parent == "port"
+        ));
+
+        assert_eq!(
+            local_path("config/secrets.toml"),
+            "config/secrets.local.toml"
+        );
+        assert_eq!(local_path(".env"), ".env.local");
+        assert_eq!(local_path(".env.production"), ".env.production.local");
+        assert_eq!(local_path("prod.env"), "prod.local.env");
+
+        let directory = std::env::temp_dir().join(format!("glint-env-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("env.toml");
+        fs::write(&path, content).unwrap();
+        let path = path.to_str().unwrap();
+
+        insert(path, None, "token", "secret", true).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), content);
+        let local = local_path(path);
+        assert_eq!(fs::read_to_string(&local).unwrap(), "token = \"secret\"\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&local).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let data = load(path, None).unwrap();
+        assert_eq!(data["token"], "secret");
+        assert_eq!(
+            lookup(&data, "services.auth.limits.rate"),
+            Some(&Value::from(10))
+        );
+
+        // Local values work before anyone has created the shared file
+        let missing = directory.join("new.toml");
+        let missing = missing.to_str().unwrap();
+        assert!(load(missing, None).is_err());
+        assert!(load_local(missing, None).unwrap().is_empty());
+        insert(missing, None, "token", "mine", true).unwrap();
+        assert_eq!(load_local(missing, None).unwrap()["token"], "mine");
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,34 +1,81 @@
-use crate::masking::mask_json;
+use crate::assertions::{self, AssertionOutcome};
+use crate::condition::{Condition, ConditionError, Context};
+use crate::env_file;
+use crate::filters::{self, FilterError};
+use crate::generators::{self, GeneratorError};
+use crate::masking::{mask_json, mask_text, MaskingRule};
 use crate::options::Options;
-use crate::request::{Dependencies, Dependency, Request, RequestBody};
-use crate::resolvers::env_var_resolver::EnvVarResolver;
-use crate::resolvers::one_password_resolver::{OnePasswordResolver, OnePasswordResolverError};
-use crate::resolvers::prompt_resolver::{PromptResolver, PromptResolverError};
+use crate::pagination::{PaginationError, PaginationMode};
+use crate::planner::{Plan, PlanError};
+use crate::request::{
+    find_capture, Config, Dependencies, Dependency, Environment, Foreach, Request, RequestBody,
+    ResponseTarget, Settings,
+};
 use crate::resolvers::response_resolver::ResponseResolver;
-use crate::resolvers::Resolver;
+use crate::resolvers::{Registry, ResolverError};
 use crate::response::Response;
+use crate::retry::RetryPolicy;
+use crate::template::{Placeholder, Segment, Template, TemplateError};
 use bat::PrettyPrinter;
 use console::style;
-use lazy_static::lazy_static;
-use once_cell::sync::Lazy;
-use regex::Regex;
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue, InvalidHeaderValue};
-use reqwest::Client;
+use futures::stream::{self, Stream, StreamExt};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, InvalidHeaderValue, CONTENT_TYPE};
+use reqwest::redirect::Policy;
+use reqwest::{Client, RequestBuilder};
+use serde_json::Value;
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tracing::{debug, error};
-
-lazy_static! {
-    static ref PLACEHOLDER_REGEX: Regex = Regex::new(r"\{(\w+)\}").unwrap();
-}
+use tokio::sync::{Mutex as AsyncMutex, OnceCell, Semaphore, SemaphorePermit};
+use tracing::{debug, error, warn};
 
 #[derive(Error, Debug)]
 pub enum ExecutionError {
     #[error("Request `{request:?}` was not found in history")]
     RequestNotFound { request: String },
+    #[error("Request `{request:?}` iterates over `{source_request:?}`, which did not resolve to an array")]
+    ForeachNotArray {
+        request: String,
+        source_request: String,
+    },
+    #[error("Environment `{environment:?}` is not declared in the collection")]
+    EnvironmentNotFound { environment: String },
+    #[error("Dependency `{placeholder:?}` of request `{request:?}` is invalid: {source}")]
+    InvalidDependency {
+        request: String,
+        placeholder: String,
+        source: ResolverError,
+    },
+    #[error(transparent)]
+    PlanningFailed(#[from] PlanError),
     #[error(transparent)]
     DependencyResolutionFailed(#[from] DependencyResolutionError),
+    #[error("Request `{request:?}` failed: {source}")]
+    RequestFailed {
+        request: String,
+        source: reqwest::Error,
+    },
+    #[error("Request `{request:?}` timed out after {timeout:?}")]
+    Timeout { request: String, timeout: Duration },
+    #[error("Request `{request:?}` did not satisfy `{condition}` within {timeout:?} ({attempts} attempts, last status: {status})")]
+    PollTimedOut {
+        request: String,
+        condition: String,
+        timeout: Duration,
+        attempts: u32,
+        status: reqwest::StatusCode,
+    },
+    #[error(transparent)]
+    PaginationFailed(#[from] PaginationError),
+    #[error("Condition `{condition}` of request `{request:?}` could not be evaluated: {source}")]
+    ConditionFailed {
+        request: String,
+        condition: String,
+        source: ConditionError,
+    },
+    #[error("{failed} assertion(s) failed")]
+    AssertionsFailed { failed: usize },
     #[error("Unknown error: `{0:?}`")]
     Unknown(String),
 }
@@ -36,78 +83,336 @@ pub enum ExecutionError {
 #[derive(Error, Debug)]
 pub enum DependencyResolutionError {
     #[error(transparent)]
-    OnePasswordDependencyFailed(#[from] OnePasswordResolverError),
-    #[error(transparent)]
-    PromptDependencyFailed(#[from] PromptResolverError),
-    #[error("Not yet implemented: `{0}`")]
-    NotImplemented(String),
+    ResolverFailed(#[from] ResolverError),
+    #[error("Could not get {target} from request `{request:?}`: {message}")]
+    ResponseValue {
+        request: String,
+        target: String,
+        message: String,
+    },
     #[error("Dependency definition for `{placeholder:?}` could not be found")]
     PlaceholderDefinitionNotFound { placeholder: String },
+    #[error(transparent)]
+    InvalidTemplate(#[from] TemplateError),
+    #[error(transparent)]
+    FilterFailed(#[from] FilterError),
+    #[error(transparent)]
+    GeneratorFailed(#[from] GeneratorError),
+    #[error("Request `{request:?}` was skipped because its `when` condition was false")]
+    RequestSkipped { request: String },
 }
 
-// Cache for loaded env files
-static ENV_FILES_CACHE: Lazy<Mutex<HashMap<String, HashMap<String, String>>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
+/// Placeholder values scoped to a single execution of a request, such as the
+/// current element of a `foreach`
+pub type Variables = HashMap<String, Value>;
+
+/// The responses of a request, or `None` if its `when` condition was false
+/// and it was skipped
+pub type Outcome = Option<Vec<Response>>;
+
+/// A request whose placeholders have all been resolved, ready to be sent
+#[derive(Clone, Debug)]
+pub struct PreparedRequest {
+    pub method: reqwest::Method,
+    pub url: String,
+    pub headers: HeaderMap,
+    pub body: Option<RequestBody>,
+}
 
 #[derive(Debug)]
 pub struct Executor {
-    requests: HashMap<String, Request>,
+    requests: Vec<Request>,
+    settings: Settings,
+    environment: Option<Environment>,
     options: Options,
     http: Client,
-    env_var_resolver: EnvVarResolver,
-    prompt_resolver: PromptResolver,
-    response_resolver: ResponseResolver,
-    one_password_resolver: OnePasswordResolver,
+    // Clients for requests that override the connect timeout or don't follow
+    // redirects, which can only be set per client
+    clients: Mutex<HashMap<(Option<u64>, bool), Client>>,
+    resolvers: Registry,
+    response_resolver: AsyncMutex<ResponseResolver>,
+    // One cell per request, so a request that several others depend on is
+    // only ever sent once, however many of them are running at the same time
+    executions: AsyncMutex<HashMap<String, Arc<OnceCell<Outcome>>>>,
+    // Requests in flight, up to `--jobs` across the whole run, `foreach`
+    // iterations included
+    in_flight: Semaphore,
 }
 
 impl Executor {
-    pub fn new(requests: Vec<Request>, options: Options) -> Self {
-        Self {
-            requests: requests
-                .into_iter()
-                .map(|request| (request.name.clone(), request))
-                .collect(),
+    pub fn new(
+        config: Config,
+        options: Options,
+        resolvers: Registry,
+    ) -> Result<Self, ExecutionError> {
+        // Report unknown sources and malformed dependencies before anything
+        // is sent
+        for request in &config.requests {
+            for (placeholder, dependency) in request.dependencies.iter().flatten() {
+                if let Dependency::Resolver { source, arguments } = dependency {
+                    resolvers.check(source, arguments).map_err(|source| {
+                        ExecutionError::InvalidDependency {
+                            request: request.name.clone(),
+                            placeholder: placeholder.clone(),
+                            source,
+                        }
+                    })?;
+                }
+            }
+        }
+
+        let http = build_client(config.settings.connect_timeout_ms, true)?;
+
+        let environment = match &options.env {
+            Some(name) => Some(config.environments.get(name).cloned().ok_or_else(|| {
+                ExecutionError::EnvironmentNotFound {
+                    environment: name.clone(),
+                }
+            })?),
+            None => None,
+        };
+
+        let in_flight = Semaphore::new(options.jobs.max(1));
+
+        Ok(Self {
+            requests: config.requests,
+            settings: config.settings,
+            environment,
             options,
-            http: Client::new(),
-            env_var_resolver: EnvVarResolver::new(),
-            prompt_resolver: PromptResolver::new(),
-            response_resolver: ResponseResolver::new(),
-            one_password_resolver: OnePasswordResolver::new(),
+            http,
+            clients: Mutex::new(HashMap::new()),
+            resolvers,
+            response_resolver: AsyncMutex::new(ResponseResolver::new()),
+            executions: AsyncMutex::new(HashMap::new()),
+            in_flight,
+        })
+    }
+
+    /// Plan a single request and its dependencies, or the whole collection
+    pub fn plan(&self, request: Option<&str>) -> Result<Plan, PlanError> {
+        match request {
+            Some(request_name) => Plan::for_request(&self.requests, request_name),
+            None => Plan::for_collection(&self.requests),
         }
     }
 
-    pub async fn execute(&mut self) -> Result<(), ExecutionError> {
-        match &self.options.request {
-            Some(request_name) => {
-                let request = self
-                    .requests
-                    .get(request_name)
-                    .ok_or(ExecutionError::RequestNotFound {
-                        request: request_name.to_owned(),
-                    })?
-                    .clone();
+    pub async fn execute(&self) -> Result<(), ExecutionError> {
+        // Plan up front so that cycles and unknown requests are reported
+        // before anything is sent
+        let plan = self.plan(self.options.request.as_deref())?;
 
-                let response = self.execute_request(request.clone()).await?;
+        if self.options.plan {
+            print!("{}", plan);
+            return Ok(());
+        }
+
+        if self.options.dry_run {
+            let request_names = match &self.options.request {
+                Some(request_name) => std::slice::from_ref(request_name),
+                None => plan.order(),
+            };
 
-                self.render_output(response).await?;
+            for request_name in request_names {
+                let request = self.find_request(request_name)?;
+
+                // The array to iterate over would need a network call, so a
+                // dry run shows a single iteration with stand-in elements
+                let variables = match &request.foreach {
+                    Some(foreach) => Variables::from([(foreach.name.clone(), Value::Null)]),
+                    None => Variables::new(),
+                };
+
+                let prepared = self.prepare_request(request, &variables).await?;
+                self.render_prepared(request, prepared)?;
             }
-            None => {
-                let cloned_requests: Vec<_> = self.requests.values().cloned().collect();
+            return Ok(());
+        }
 
-                for request in cloned_requests {
-                    let response = self.execute_request(request.clone()).await?;
+        let mut failed = 0;
+
+        match &self.options.request {
+            Some(request_name) => {
+                let outcome = self.execute_once(request_name).await?;
 
-                    self.render_output(response).await?;
+                failed += self.render_outcome(request_name, outcome).await?;
+            }
+            None => {
+                let mut outcomes = self.execute_in_order(plan.order());
+                while let Some(outcome) = outcomes.next().await {
+                    let (request_name, outcome) = outcome?;
+                    failed += self.render_outcome(request_name, outcome).await?;
                 }
             }
         }
+
+        if failed > 0 {
+            return Err(ExecutionError::AssertionsFailed { failed });
+        }
         Ok(())
     }
 
-    pub async fn execute_request(&mut self, request: Request) -> Result<Response, ExecutionError> {
+    /// Execute requests in plan order, up to `jobs` at a time. A request
+    /// whose dependency is still in flight waits for it, and results are
+    /// yielded in plan order regardless of which one finishes first.
+    fn execute_in_order<'a>(
+        &'a self,
+        request_names: &'a [String],
+    ) -> impl Stream<Item = Result<(&'a str, Outcome), ExecutionError>> + 'a {
+        stream::iter(request_names)
+            .map(move |request_name| async move {
+                let outcome = self.execute_once(request_name).await?;
+                Ok((request_name.as_str(), outcome))
+            })
+            .buffered(self.options.jobs.max(1))
+    }
+
+    /// Execute a request unless it has already been executed (or is being
+    /// executed right now), in which case its responses are reused
+    async fn execute_once(&self, request_name: &str) -> Result<Outcome, ExecutionError> {
+        let execution = self
+            .executions
+            .lock()
+            .await
+            .entry(request_name.to_owned())
+            .or_default()
+            .clone();
+
+        execution
+            .get_or_try_init(|| async {
+                let request = self.find_request(request_name)?.clone();
+                self.run_request(request).await
+            })
+            .await
+            .cloned()
+    }
+
+    pub fn find_request(&self, request_name: &str) -> Result<&Request, ExecutionError> {
+        self.requests
+            .iter()
+            .find(|request| request.name == request_name)
+            .ok_or(ExecutionError::RequestNotFound {
+                request: request_name.to_owned(),
+            })
+    }
+
+    /// Execute a request, once per element if it iterates with `foreach`,
+    /// unless its `when` condition is false
+    async fn run_request(&self, request: Request) -> Result<Outcome, ExecutionError> {
+        if let Some(condition) = &request.when {
+            if !self
+                .check_condition(&request, condition, &Variables::new(), None)
+                .await?
+            {
+                debug!("Skipping {}: {} is false", request.name, condition);
+                return Ok(None);
+            }
+        }
+
+        let responses = match &request.foreach {
+            Some(foreach) => self.run_foreach(&request, foreach).await?,
+            None => self.execute_request(&request, &Variables::new()).await?,
+        };
+
+        // Saved once all iterations are done, so history keeps element order
+        let mut response_resolver = self.response_resolver.lock().await;
+        for response in &responses {
+            response_resolver.save_to_history(response.clone());
+        }
+
+        Ok(Some(responses))
+    }
+
+    /// Evaluate a condition of `request`. Requests the condition refers to
+    /// are executed first, and `current` is the response being checked, if
+    /// any.
+    async fn check_condition(
+        &self,
+        request: &Request,
+        condition: &Condition,
+        variables: &Variables,
+        current: Option<&Response>,
+    ) -> Result<bool, ExecutionError> {
+        let condition_failed = |source| ExecutionError::ConditionFailed {
+            request: request.name.clone(),
+            condition: condition.to_string(),
+            source,
+        };
+
+        let mut responses = HashMap::new();
+        for request_name in condition.requests() {
+            // A skipped request has no response, which the condition reports
+            Box::pin(self.execute_once(request_name)).await?;
+            if let Some(response) = self.response_resolver.lock().await.latest(request_name) {
+                responses.insert(request_name.to_string(), response.clone());
+            }
+        }
+
+        let mut values = HashMap::new();
+        for placeholder in condition.placeholders() {
+            let value = self
+                .resolve_placeholders(
+                    &format!("{{{}}}", placeholder),
+                    request.dependencies.as_ref(),
+                    variables,
+                )
+                .await?;
+            values.insert(placeholder.to_string(), value);
+        }
+
+        condition
+            .evaluate(&Context {
+                variables: &values,
+                responses: &responses,
+                current,
+            })
+            .map_err(condition_failed)
+    }
+
+    async fn run_foreach(
+        &self,
+        request: &Request,
+        foreach: &Foreach,
+    ) -> Result<Vec<Response>, ExecutionError> {
+        let value = self
+            .resolve_response_value(&foreach.request, &foreach.target)
+            .await?;
+        let items = match value {
+            Value::Array(items) => items,
+            _ => {
+                return Err(ExecutionError::ForeachNotArray {
+                    request: request.name.clone(),
+                    source_request: foreach.request.clone(),
+                })
+            }
+        };
+        debug!("Iterating {} over {} items", request.name, items.len());
+
+        // Every iteration starts at once and waits for a `--jobs` slot to
+        // send, so one waiting to retry doesn't hold the others back
+        let iterations = items.into_iter().map(|item| {
+            let variables = Variables::from([(foreach.name.clone(), item)]);
+            async move { self.execute_request(request, &variables).await }
+        });
+        let responses = futures::future::try_join_all(iterations).await?;
+        Ok(responses.into_iter().flatten().collect())
+    }
+
+    /// Resolve every placeholder in a request, without sending it
+    pub async fn prepare_request(
+        &self,
+        request: &Request,
+        variables: &Variables,
+    ) -> Result<PreparedRequest, ExecutionError> {
+        let method = reqwest::Method::from_bytes(request.method.as_bytes())
+            .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
+
+        self.prefetch_dependencies(request)
+            .await
+            .map_err(DependencyResolutionError::from)?;
+
         // Resolve URL
         let url = self
-            .resolve_placeholders(&request.url, request.dependencies.as_ref())
+            .resolve_url(&request.url, request.dependencies.as_ref(), variables)
             .await?;
         debug!(url);
 
@@ -115,13 +420,19 @@ impl Executor {
             let mut resolved_headers = HeaderMap::new();
             for (key, value) in header_map {
                 let resolved_key = self
-                    .resolve_placeholders(key, request.dependencies.as_ref())
+                    .resolve_optional(key, request.dependencies.as_ref(), variables)
                     .await
                     .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
                 let resolved_value = self
-                    .resolve_placeholders(value, request.dependencies.as_ref())
+                    .resolve_optional(value, request.dependencies.as_ref(), variables)
                     .await
                     .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
+                // Leave out headers with an optional placeholder that
+                // resolved to nothing
+                let (Some(resolved_key), Some(resolved_value)) = (resolved_key, resolved_value)
+                else {
+                    continue;
+                };
                 let header_name = HeaderName::from_bytes(resolved_key.as_bytes())
                     .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
                 resolved_headers.insert(
@@ -142,36 +453,25 @@ impl Executor {
         // Resolve the request body, if it exists
         let body = match &request.body {
             Some(RequestBody::Text(text)) => Some(RequestBody::Text(
-                self.resolve_placeholders(text, request.dependencies.as_ref())
+                self.resolve_placeholders(text, request.dependencies.as_ref(), variables)
                     .await
                     .map_err(|error| ExecutionError::Unknown(error.to_string()))?,
             )),
-            Some(RequestBody::Json(json)) => {
-                // Serialize the entire JSON value to a string
-                let json_string = serde_json::to_string(&json)
-                    .map_err(|e| ExecutionError::Unknown(format!("Serialization error: {}", e)))?;
-
-                // Apply the resolve_placeholders function
-                let resolved_string = self
-                    .resolve_placeholders(&json_string, request.dependencies.as_ref())
-                    .await
-                    .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
-
-                // Deserialize the resolved string back into a JSON value
-                let resolved_value = serde_json::from_str(&resolved_string).map_err(|e| {
-                    ExecutionError::Unknown(format!("Deserialization error: {}", e))
-                })?;
-
-                Some(RequestBody::Json(resolved_value))
-            }
+            Some(RequestBody::Json(json)) => Some(RequestBody::Json(
+                self.resolve_json(json, request.dependencies.as_ref(), variables)
+                    .await?
+                    .unwrap_or(Value::Null),
+            )),
             Some(RequestBody::Form(hash_map)) => {
                 let mut resolved_form = HashMap::new();
                 for (key, value) in hash_map {
                     let resolved_value = self
-                        .resolve_placeholders(value, request.dependencies.as_ref())
+                        .resolve_optional(value, request.dependencies.as_ref(), variables)
                         .await
                         .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
-                    resolved_form.insert(key.clone(), resolved_value);
+                    if let Some(resolved_value) = resolved_value {
+                        resolved_form.insert(key.clone(), resolved_value);
+                    }
                 }
                 Some(RequestBody::Form(resolved_form))
             }
@@ -179,48 +479,331 @@ impl Executor {
         };
         debug!("{:?}", body);
 
-        // Execute the request and capture the response
-        let response = {
-            let builder = self
-                .http
-                .request(
-                    reqwest::Method::from_bytes(request.method.as_bytes())
-                        .map_err(|error| ExecutionError::Unknown(error.to_string()))?,
-                    &url,
-                )
-                .headers(headers);
+        Ok(PreparedRequest {
+            method,
+            url,
+            headers,
+            body,
+        })
+    }
 
-            let builder = match body {
-                Some(RequestBody::Text(text)) => builder.body(text),
-                Some(RequestBody::Json(json)) => builder.json(&json),
-                Some(RequestBody::Form(form)) => builder.form(&form),
-                None => builder,
-            };
-            debug!("{:?}", builder);
+    /// Execute a request, following its pages if it is paginated
+    pub async fn execute_request(
+        &self,
+        request: &Request,
+        variables: &Variables,
+    ) -> Result<Vec<Response>, ExecutionError> {
+        let prepared = self.prepare_request(request, variables).await?;
 
-            builder
-                .send()
-                .await
-                .map_err(|error| ExecutionError::Unknown(error.to_string()))?
+        let Some(poll) = &request.poll else {
+            return self.fetch(request, prepared).await;
+        };
+
+        // Deadline for starting another attempt, so a slow last attempt can
+        // take a little longer than the poll timeout
+        let started = Instant::now();
+        let mut attempt = 1;
+        loop {
+            let responses = self.fetch(request, prepared.clone()).await?;
+            let response = responses
+                .last()
+                .expect("a request fetches at least one response");
+
+            if self
+                .check_condition(request, &poll.until, variables, Some(response))
+                .await?
+            {
+                debug!(
+                    "{} satisfied {} after {} attempts",
+                    request.name, poll.until, attempt
+                );
+                return Ok(responses);
+            }
+
+            if poll.out_of_attempts(attempt) || started.elapsed() + poll.interval() > poll.timeout()
+            {
+                return Err(ExecutionError::PollTimedOut {
+                    request: request.name.clone(),
+                    condition: poll.until.to_string(),
+                    timeout: poll.timeout(),
+                    attempts: attempt,
+                    status: response.status,
+                });
+            }
+
+            debug!("Polling {} again in {:?}", request.name, poll.interval());
+            tokio::time::sleep(poll.interval()).await;
+            attempt += 1;
+        }
+    }
+
+    /// Send a prepared request, fetching every page if it's paginated
+    async fn fetch(
+        &self,
+        request: &Request,
+        prepared: PreparedRequest,
+    ) -> Result<Vec<Response>, ExecutionError> {
+        let Some(pagination) = &request.pagination else {
+            return Ok(vec![self.send_prepared(request, prepared).await?]);
         };
 
+        let mut pages = Vec::new();
+        while let Some(url) = pagination.next_url(&pages, &prepared.url)? {
+            let page = PreparedRequest {
+                url,
+                ..prepared.clone()
+            };
+            pages.push(self.send_prepared(request, page).await?);
+        }
+        debug!("Fetched {} pages of {}", pages.len(), request.name);
+
+        Ok(match pagination.mode {
+            PaginationMode::Merge => vec![pagination.merge(pages)?],
+            PaginationMode::Pages => pages,
+        })
+    }
+
+    async fn send_prepared(
+        &self,
+        request: &Request,
+        prepared: PreparedRequest,
+    ) -> Result<Response, ExecutionError> {
+        // Execute the request and capture the response
+        let builder = self.build_request(request, prepared)?;
+        let (response, started, _permit) = self.send_with_retry(request, builder).await?;
+
         let response = Response {
             request: request.clone(),
             headers: response.headers().clone(),
             status: response.status(),
+            url: response.url().clone(),
             text: response
                 .text()
                 .await
-                .map_err(|error| ExecutionError::Unknown(error.to_string()))?,
+                .map_err(|error| self.request_failed(request, error))?,
+            elapsed: started.elapsed(),
         };
         debug!("{:?}", response);
 
-        self.response_resolver.save_to_history(response.clone());
-
         Ok(response)
     }
 
-    async fn render_output(&mut self, response: Response) -> Result<(), ExecutionError> {
+    /// Build a prepared request with the client and timeout of `request`
+    pub fn build_request(
+        &self,
+        request: &Request,
+        prepared: PreparedRequest,
+    ) -> Result<RequestBuilder, ExecutionError> {
+        let builder = self
+            .client_for(request)?
+            .request(prepared.method, &prepared.url)
+            .headers(prepared.headers);
+
+        let builder = match self.timeout_for(request) {
+            Some(timeout) => builder.timeout(timeout),
+            None => builder,
+        };
+
+        let builder = match prepared.body {
+            Some(RequestBody::Text(text)) => builder.body(text),
+            Some(RequestBody::Json(json)) => builder.json(&json),
+            Some(RequestBody::Form(form)) => builder.form(&form),
+            None => builder,
+        };
+        debug!("{:?}", builder);
+
+        Ok(builder)
+    }
+
+    /// The client to send `request` with, honoring its connect timeout and
+    /// redirect setting
+    fn client_for(&self, request: &Request) -> Result<Client, ExecutionError> {
+        if request.connect_timeout_ms.is_none() && request.follow_redirects {
+            return Ok(self.http.clone());
+        }
+
+        let key = (
+            request
+                .connect_timeout_ms
+                .or(self.settings.connect_timeout_ms),
+            request.follow_redirects,
+        );
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+        let client = build_client(key.0, key.1)?;
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    /// The total timeout for `request`: the --timeout flag wins over the
+    /// request, which wins over the collection settings
+    fn timeout_for(&self, request: &Request) -> Option<Duration> {
+        self.options
+            .timeout
+            .or(request.timeout_ms)
+            .or(self.settings.timeout_ms)
+            .map(Duration::from_millis)
+    }
+
+    fn request_failed(&self, request: &Request, error: reqwest::Error) -> ExecutionError {
+        let timeout = if error.is_connect() {
+            request
+                .connect_timeout_ms
+                .or(self.settings.connect_timeout_ms)
+                .map(Duration::from_millis)
+        } else {
+            self.timeout_for(request)
+        };
+
+        match timeout {
+            Some(timeout) if error.is_timeout() => ExecutionError::Timeout {
+                request: request.name.clone(),
+                timeout,
+            },
+            _ => ExecutionError::RequestFailed {
+                request: request.name.clone(),
+                source: error,
+            },
+        }
+    }
+
+    /// Send a request, retrying it according to its retry policy. Returns the
+    /// final response along with the moment its attempt started, and its
+    /// `--jobs` slot, to be held until the body has been read.
+    async fn send_with_retry(
+        &self,
+        request: &Request,
+        builder: RequestBuilder,
+    ) -> Result<(reqwest::Response, Instant, SemaphorePermit<'_>), ExecutionError> {
+        let policy = request.retry.clone().unwrap_or_else(RetryPolicy::none);
+        let mut attempt = 1;
+
+        loop {
+            let attempt_builder = builder.try_clone().ok_or_else(|| {
+                ExecutionError::Unknown("Request body cannot be sent more than once".to_string())
+            })?;
+            // Each attempt takes a slot of its own, so that waiting to retry
+            // doesn't keep other requests from being sent
+            let permit = self
+                .in_flight
+                .acquire()
+                .await
+                .expect("the semaphore is never closed");
+            let started = Instant::now();
+            let retries_left = attempt < policy.max_attempts;
+
+            let delay = match attempt_builder.send().await {
+                Ok(response) if retries_left && policy.retries_status(response.status()) => {
+                    let Some(delay) = policy.delay_after(response.headers(), attempt) else {
+                        warn!(
+                            "Request {} returned {} and asked to wait longer than max_delay_ms, not retrying",
+                            request.name,
+                            response.status()
+                        );
+                        return Ok((response, started, permit));
+                    };
+                    warn!(
+                        "Request {} returned {}, retrying in {:?} (attempt {} of {})",
+                        request.name,
+                        response.status(),
+                        delay,
+                        attempt,
+                        policy.max_attempts
+                    );
+                    delay
+                }
+                Ok(response) => return Ok((response, started, permit)),
+                Err(error) if retries_left && policy.transport_errors => {
+                    let delay = policy.delay_for(attempt);
+                    warn!(
+                        "Request {} failed: {}, retrying in {:?} (attempt {} of {})",
+                        request.name, error, delay, attempt, policy.max_attempts
+                    );
+                    delay
+                }
+                Err(error) => return Err(self.request_failed(request, error)),
+            };
+
+            drop(permit);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Print the responses of a request, or a note that it was skipped, and
+    /// return how many assertions failed
+    async fn render_outcome(
+        &self,
+        request_name: &str,
+        outcome: Outcome,
+    ) -> Result<usize, ExecutionError> {
+        match outcome {
+            Some(responses) => self.render_responses(responses).await,
+            None => {
+                let request = self.find_request(request_name)?;
+                let condition = request
+                    .when
+                    .as_ref()
+                    .map(|condition| condition.to_string())
+                    .unwrap_or_default();
+
+                if self.options.raw_output {
+                    println!("SKIP: {} (when {})", request_name, condition);
+                } else {
+                    println!(
+                        "{} {} {}",
+                        style(" SKIP ").on_white().black(),
+                        style(request_name).bold(),
+                        style(format!("(when {})", condition)).dim()
+                    );
+                }
+                Ok(0)
+            }
+        }
+    }
+
+    /// Print responses along with their assertion reports, and return how
+    /// many assertions failed
+    async fn render_responses(&self, responses: Vec<Response>) -> Result<usize, ExecutionError> {
+        let mut failed = 0;
+
+        for response in responses {
+            let outcomes = assertions::check_all(&response);
+            self.render_output(response).await?;
+            failed += self.render_assertions(&outcomes);
+        }
+
+        Ok(failed)
+    }
+
+    /// Print a pass/fail line per assertion and return how many failed
+    fn render_assertions(&self, outcomes: &[AssertionOutcome]) -> usize {
+        for outcome in outcomes {
+            match (&outcome.failure, self.options.raw_output) {
+                (None, true) => println!("PASS: {}", outcome.description),
+                (Some(failure), true) => {
+                    println!("FAIL: {} ({})", outcome.description, failure)
+                }
+                (None, false) => println!(
+                    "{} {}",
+                    style(" PASS ").on_green().black(),
+                    outcome.description
+                ),
+                (Some(failure), false) => println!(
+                    "{} {} {}",
+                    style(" FAIL ").on_red().black(),
+                    style(&outcome.description).bold(),
+                    style(format!("({})", failure)).dim()
+                ),
+            }
+        }
+
+        outcomes.iter().filter(|outcome| !outcome.passed()).count()
+    }
+
+    async fn render_output(&self, response: Response) -> Result<(), ExecutionError> {
         if !self.options.hide_status {
             if self.options.raw_output {
                 println!(
@@ -250,227 +833,1337 @@ impl Executor {
         }
 
         if self.options.show_headers {
-            let mut headers = response.headers.clone();
-
-            if !self.options.disable_masking {
-                for (_key, value) in &mut headers {
-                    if let Some(value_str) = value.to_str().ok() {
-                        let masked_value = mask_json(
-                            serde_json::json!(value_str),
-                            &response.request.masking_rules,
-                        )
-                        .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
-                        *value = HeaderValue::from_str(masked_value.as_str().unwrap())
-                            .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
-                    }
-                }
+            self.render_headers(&response.headers, &response.request.masking_rules)?;
+        }
+
+        if !self.options.hide_body {
+            match serde_json::from_str::<Value>(&response.text) {
+                Ok(body) => self.render_json(body, &response.request.masking_rules)?,
+                // HTML pages, redirects and other bodies are printed as text
+                Err(_) => self.render_plain(&response)?,
             }
+        }
+
+        Ok(())
+    }
+
+    /// Print a body that isn't JSON. Masking rules for `$` apply to the
+    /// whole text, like they do to header values.
+    fn render_plain(&self, response: &Response) -> Result<(), ExecutionError> {
+        let text = self.masked(&response.text, &response.request.masking_rules);
+
+        let content_type = response
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        let language = if content_type.contains("html") {
+            "html"
+        } else if content_type.contains("xml") {
+            "xml"
+        } else {
+            "txt"
+        };
+        self.render_text(&text, language)
+    }
 
+    /// Print a request as it would be sent, without sending it
+    fn render_prepared(
+        &self,
+        request: &Request,
+        prepared: PreparedRequest,
+    ) -> Result<(), ExecutionError> {
+        let url = self.masked(&prepared.url, &request.masking_rules);
+        if self.options.raw_output {
+            println!("{} {}", prepared.method, url);
+        } else {
+            println!(
+                "{} {}",
+                style(format!(" {} ", prepared.method)).on_blue().black(),
+                style(&url).bold(),
+            );
+        }
+
+        // Whether the request would be skipped or polled again depends on
+        // responses a dry run doesn't have, so conditions are only shown
+        let conditions = request
+            .when
+            .iter()
+            .map(|condition| format!("when {}", condition))
+            .chain(
+                request
+                    .poll
+                    .iter()
+                    .map(|poll| format!("poll until {}", poll.until)),
+            );
+        for condition in conditions {
             if self.options.raw_output {
-                for (key, value) in headers {
-                    println!(
-                        "{}: {}",
-                        key.as_ref().map(|k| k.as_str()).unwrap_or(""),
-                        value.to_str().unwrap_or("")
-                    );
-                }
+                println!("{}", condition);
             } else {
-                let mut headers_formatted = String::new();
-                for (key, value) in headers {
-                    let key_str = key.as_ref().map(|k| k.as_str()).unwrap_or("");
-                    let value_str = value.to_str().unwrap_or("");
-                    headers_formatted.push_str(&format!("{}: {}\n", key_str, value_str));
+                println!("{}", style(condition).dim());
+            }
+        }
+
+        self.render_headers(&prepared.headers, &request.masking_rules)?;
+
+        match prepared.body {
+            Some(RequestBody::Json(json)) => self.render_json(json, &request.masking_rules)?,
+            Some(RequestBody::Form(form)) => {
+                let mut fields = serde_json::json!(form);
+                if !self.options.disable_masking {
+                    fields = mask_json(fields, &request.masking_rules)
+                        .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
                 }
 
-                PrettyPrinter::new()
-                    .input_from_bytes(headers_formatted.as_bytes())
-                    .language("toml")
-                    .print()
-                    .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
+                let mut form_formatted = String::new();
+                for (key, value) in fields.as_object().into_iter().flatten() {
+                    let value_str = value.as_str().unwrap_or_default();
+                    form_formatted.push_str(&format!("{} = {}\n", key, value_str));
+                }
+                self.render_text(&form_formatted, "toml")?;
+            }
+            Some(RequestBody::Text(text)) => {
+                self.render_text(&self.masked(&text, &request.masking_rules), "plain")?
             }
+            None => {}
         }
 
-        if !self.options.hide_body {
-            let mut body = serde_json::from_str::<serde_json::Value>(&response.text)
-                .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
+        Ok(())
+    }
 
-            if !self.options.disable_masking {
-                body = mask_json(body, &response.request.masking_rules)
-                    .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
-            }
+    /// Apply the masking rules for `$` to text that isn't JSON, such as a URL
+    /// or a text body, unless masking is disabled
+    fn masked(&self, text: &str, masking_rules: &[MaskingRule]) -> String {
+        if self.options.disable_masking {
+            text.to_string()
+        } else {
+            mask_text(text, masking_rules)
+        }
+    }
 
-            if self.options.raw_output {
-                println!(
-                    "{}",
-                    serde_json::to_string(&body)
-                        .map_err(|error| ExecutionError::Unknown(error.to_string()))?
-                );
-            } else {
-                if let Ok(pretty_json) = serde_json::to_string_pretty(&body) {
-                    PrettyPrinter::new()
-                        .input_from_bytes(pretty_json.as_bytes())
-                        .language("json")
-                        .print()
+    fn render_headers(
+        &self,
+        headers: &HeaderMap,
+        masking_rules: &[MaskingRule],
+    ) -> Result<(), ExecutionError> {
+        let mut headers = headers.clone();
+
+        if !self.options.disable_masking {
+            for (_key, value) in &mut headers {
+                if let Ok(value_str) = value.to_str() {
+                    let masked_value = mask_json(serde_json::json!(value_str), masking_rules)
                         .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
-                } else {
-                    PrettyPrinter::new()
-                        .input_from_bytes(response.text.as_bytes())
-                        .language("plain")
-                        .print()
+                    *value = HeaderValue::from_str(masked_value.as_str().unwrap())
                         .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
                 }
             }
         }
 
+        let mut headers_formatted = String::new();
+        for (key, value) in headers {
+            let key_str = key.as_ref().map(|k| k.as_str()).unwrap_or("");
+            let value_str = value.to_str().unwrap_or("");
+            headers_formatted.push_str(&format!("{}: {}\n", key_str, value_str));
+        }
+
+        self.render_text(&headers_formatted, "toml")
+    }
+
+    fn render_json(
+        &self,
+        mut body: serde_json::Value,
+        masking_rules: &[MaskingRule],
+    ) -> Result<(), ExecutionError> {
+        if !self.options.disable_masking {
+            body = mask_json(body, masking_rules)
+                .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
+        }
+
+        if self.options.raw_output {
+            println!(
+                "{}",
+                serde_json::to_string(&body)
+                    .map_err(|error| ExecutionError::Unknown(error.to_string()))?
+            );
+            Ok(())
+        } else {
+            let pretty_json = serde_json::to_string_pretty(&body)
+                .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
+            self.render_text(&pretty_json, "json")
+        }
+    }
+
+    /// Print text as is for raw output, or highlighted as `language` otherwise
+    fn render_text(&self, text: &str, language: &str) -> Result<(), ExecutionError> {
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        if self.options.raw_output {
+            print!("{}", text);
+            if !text.ends_with('\n') {
+                println!();
+            }
+            return Ok(());
+        }
+
+        PrettyPrinter::new()
+            .input_from_bytes(text.as_bytes())
+            .language(language)
+            .print()
+            .map_err(|error| ExecutionError::Unknown(error.to_string()))?;
         Ok(())
     }
 
+    /// Resolve every placeholder in a template. Optional placeholders that
+    /// resolve to nothing are left empty.
     async fn resolve_placeholders(
-        &mut self,
+        &self,
+        template: &str,
+        request_dependencies: Option<&Dependencies>,
+        variables: &Variables,
+    ) -> Result<String, DependencyResolutionError> {
+        let (resolved, _) = self
+            .resolve_template(template, request_dependencies, variables)
+            .await?;
+        Ok(resolved)
+    }
+
+    /// Resolve every placeholder in a template, or return `None` if an
+    /// optional placeholder resolved to nothing, so that whatever holds the
+    /// template (a header, form field or query parameter) can be dropped
+    async fn resolve_optional(
+        &self,
+        template: &str,
+        request_dependencies: Option<&Dependencies>,
+        variables: &Variables,
+    ) -> Result<Option<String>, DependencyResolutionError> {
+        let (resolved, complete) = self
+            .resolve_template(template, request_dependencies, variables)
+            .await?;
+        Ok(complete.then_some(resolved))
+    }
+
+    /// Resolve every placeholder in a template, and tell whether every
+    /// optional placeholder resolved to something
+    async fn resolve_template(
+        &self,
         template: &str,
         request_dependencies: Option<&Dependencies>,
+        variables: &Variables,
+    ) -> Result<(String, bool), DependencyResolutionError> {
+        let segments = Template::parse(template)?.segments;
+        self.resolve_segments(&segments, request_dependencies, variables)
+            .await
+    }
+
+    /// Resolve the segments of a parsed template, and tell whether every
+    /// optional placeholder resolved to something
+    async fn resolve_segments(
+        &self,
+        segments: &[Segment],
+        request_dependencies: Option<&Dependencies>,
+        variables: &Variables,
+    ) -> Result<(String, bool), DependencyResolutionError> {
+        let mut resolved = String::new();
+        let mut complete = true;
+
+        // Replace each placeholder in a single pass, so that resolved values
+        // are never themselves mistaken for placeholders
+        for segment in segments {
+            match segment {
+                Segment::Literal(literal) => resolved.push_str(literal),
+                Segment::Placeholder(placeholder) => match self
+                    .resolve_placeholder(placeholder, request_dependencies, variables)
+                    .await?
+                {
+                    Some(value) => resolved.push_str(&value_to_text(value)),
+                    None => complete = false,
+                },
+            }
+        }
+
+        Ok((resolved, complete))
+    }
+
+    /// Resolve the URL, dropping query parameters whose optional
+    /// placeholders resolved to nothing
+    async fn resolve_url(
+        &self,
+        url: &str,
+        request_dependencies: Option<&Dependencies>,
+        variables: &Variables,
     ) -> Result<String, DependencyResolutionError> {
-        let mut resolved = template.to_string();
+        // The query is split in the template's text only, so a `?` or `&`
+        // inside a placeholder, like `{id?}` or `{sort:-a&b}`, is left alone
+        let (base, query) = split_query(Template::parse(url)?.segments);
+
+        let (mut resolved, _) = self
+            .resolve_segments(&base, request_dependencies, variables)
+            .await?;
+        let mut params = Vec::new();
+        for param in query.iter().flatten() {
+            let (param, complete) = self
+                .resolve_segments(param, request_dependencies, variables)
+                .await?;
+            if complete {
+                params.push(param);
+            }
+        }
+        if !params.is_empty() {
+            resolved.push('?');
+            resolved.push_str(&params.join("&"));
+        }
 
-        // Find all placeholders in the template
-        for caps in PLACEHOLDER_REGEX.captures_iter(template) {
-            let placeholder = &caps[1]; // The name inside {}
+        Ok(resolved)
+    }
 
-            // Try to resolve the placeholder
-            let value = if let Some(dep) = request_dependencies
-                .as_ref()
-                .and_then(|deps| deps.get(placeholder))
-            {
-                self.resolve_dependency_value(dep, placeholder).await?
-            } else {
-                error!("Resolving {} from request {}", placeholder, template);
-                return Err(DependencyResolutionError::PlaceholderDefinitionNotFound {
+    /// Resolve a single placeholder of a template. Values taken from JSON (response bodies and
+    /// `foreach` elements) keep their type, unless a filter turns them into
+    /// a string.
+    ///
+    /// `{name:-fallback}` falls back to `fallback` if the placeholder can't be
+    /// resolved or resolves to nothing, and `{name?}` returns `None` instead.
+    async fn resolve_placeholder(
+        &self,
+        placeholder: &Placeholder,
+        request_dependencies: Option<&Dependencies>,
+        variables: &Variables,
+    ) -> Result<Option<Value>, DependencyResolutionError> {
+        let resolved = self
+            .lookup_placeholder(&placeholder.name, request_dependencies, variables)
+            .await;
+        let mut value = match (resolved, &placeholder.default) {
+            (Ok(value), Some(default)) if is_nothing(&value) => Value::String(default.clone()),
+            (Ok(value), None) if placeholder.optional && is_nothing(&value) => return Ok(None),
+            (Ok(value), _) => value,
+            (Err(error), Some(default)) => {
+                debug!("Falling back for {}: {}", placeholder.source, error);
+                Value::String(default.clone())
+            }
+            (Err(error), None) if placeholder.optional => {
+                debug!("Leaving out {}: {}", placeholder.source, error);
+                return Ok(None);
+            }
+            (Err(error), None) => {
+                error!("Resolving {}: {}", placeholder.source, error);
+                return Err(error);
+            }
+        };
+
+        // Pipe the value through the filters, left to right
+        for filter in &placeholder.filters {
+            value = Value::String(filters::apply(
+                filter,
+                value_to_text(value),
+                &placeholder.source,
+            )?);
+        }
+
+        Ok(Some(value))
+    }
+
+    /// Find the value of a placeholder: a generator, one of the request's
+    /// dependencies, a `foreach` variable, another request's capture or an
+    /// environment variable
+    async fn lookup_placeholder(
+        &self,
+        placeholder: &str,
+        request_dependencies: Option<&Dependencies>,
+        variables: &Variables,
+    ) -> Result<Value, DependencyResolutionError> {
+        let dependency = request_dependencies.and_then(|deps| deps.get(placeholder));
+
+        Ok(if placeholder.starts_with('$') {
+            Value::String(generators::generate(placeholder)?)
+        } else if let Some(Dependency::Template { template }) = dependency {
+            // Templates can refer to the request's other placeholders
+            Value::String(
+                Box::pin(self.resolve_placeholders(template, request_dependencies, variables))
+                    .await?,
+            )
+        } else if let Some(Dependency::Response { request, target }) = dependency {
+            self.resolve_response_value(request, target).await?
+        } else if let Some(dep) = dependency {
+            self.resolve_dependency_value(dep, placeholder).await?
+        } else if let Some(value) = self.resolve_variable(variables, placeholder)? {
+            value
+        } else if let Some((request, target)) = find_capture(&self.requests, placeholder) {
+            self.resolve_response_value(&request.name, target).await?
+        } else if let Some(value) = self.resolve_environment_value(placeholder)? {
+            value
+        } else {
+            return Err(DependencyResolutionError::PlaceholderDefinitionNotFound {
+                placeholder: placeholder.to_string(),
+            });
+        })
+    }
+
+    /// Resolve the placeholders in every string of a JSON body, keys
+    /// included. A string that is nothing but a placeholder is replaced by
+    /// the placeholder's value, type and all, so `"{count}"` can become a
+    /// number. Values embedded in longer strings are inserted as text.
+    ///
+    /// Returns `None` if an optional placeholder resolved to nothing, in
+    /// which case the value is left out of its object or array.
+    async fn resolve_json(
+        &self,
+        json: &Value,
+        request_dependencies: Option<&Dependencies>,
+        variables: &Variables,
+    ) -> Result<Option<Value>, DependencyResolutionError> {
+        Ok(match json {
+            Value::String(template) => match Template::parse(template)?.as_placeholder() {
+                Some(placeholder) => {
+                    self.resolve_placeholder(placeholder, request_dependencies, variables)
+                        .await?
+                }
+                None => self
+                    .resolve_optional(template, request_dependencies, variables)
+                    .await?
+                    .map(Value::String),
+            },
+            Value::Array(items) => {
+                let mut resolved = Vec::with_capacity(items.len());
+                for item in items {
+                    resolved.extend(
+                        Box::pin(self.resolve_json(item, request_dependencies, variables)).await?,
+                    );
+                }
+                Some(Value::Array(resolved))
+            }
+            Value::Object(map) => {
+                let mut resolved = serde_json::Map::with_capacity(map.len());
+                for (key, value) in map {
+                    let Some(key) = self
+                        .resolve_optional(key, request_dependencies, variables)
+                        .await?
+                    else {
+                        continue;
+                    };
+                    if let Some(value) =
+                        Box::pin(self.resolve_json(value, request_dependencies, variables)).await?
+                    {
+                        resolved.insert(key, value);
+                    }
+                }
+                Some(Value::Object(resolved))
+            }
+            other => Some(other.clone()),
+        })
+    }
+
+    /// Look a placeholder up in the variables of the current `foreach`
+    /// iteration. `{item}` is the element itself and `{item.owner.login}`
+    /// walks into its fields (or array indices).
+    fn resolve_variable(
+        &self,
+        variables: &Variables,
+        placeholder: &str,
+    ) -> Result<Option<Value>, DependencyResolutionError> {
+        let mut segments = placeholder.split('.');
+        let Some(mut value) = segments.next().and_then(|name| variables.get(name)) else {
+            return Ok(None);
+        };
+
+        if self.options.dry_run {
+            return Ok(Some(Value::String(format!("<{}>", placeholder))));
+        }
+
+        for segment in segments {
+            value = match value {
+                Value::Object(map) => map.get(segment),
+                Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+                _ => None,
+            }
+            .ok_or_else(|| {
+                DependencyResolutionError::PlaceholderDefinitionNotFound {
                     placeholder: placeholder.to_string(),
-                });
-            };
+                }
+            })?;
+        }
+
+        Ok(Some(value.clone()))
+    }
 
-            // Replace the placeholder in the resolved string
-            resolved = resolved.replace(&format!("{{{}}}", placeholder), &value);
+    /// Look a placeholder up in the environment selected with --env. Values
+    /// from its env file keep their type, and `{services.auth.url}` walks
+    /// into nested tables.
+    fn resolve_environment_value(
+        &self,
+        placeholder: &str,
+    ) -> Result<Option<Value>, DependencyResolutionError> {
+        let Some(environment) = &self.environment else {
+            return Ok(None);
+        };
+
+        if let Some(value) = environment.variables.get(placeholder) {
+            return Ok(Some(Value::String(value.clone())));
         }
 
-        Ok(resolved)
+        match &environment.env_file {
+            Some(env_file) => {
+                let env_data = env_file::load(env_file, environment.env_file_format)
+                    .map_err(ResolverError::failed)?;
+                Ok(env_file::lookup(&env_data, placeholder).cloned())
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Resolve a value from another request's response, executing that
+    /// request first if it hasn't been yet
+    async fn resolve_response_value(
+        &self,
+        request: &String,
+        target: &ResponseTarget,
+    ) -> Result<Value, DependencyResolutionError> {
+        // Check if the request is already resolved
+        if let Ok(value) = self
+            .response_resolver
+            .lock()
+            .await
+            .resolve_value(request, target)
+        {
+            return Ok(value);
+        }
+
+        // A dry run never sends anything, so stand in for the value
+        if self.options.dry_run {
+            return Ok(Value::String(format!("<{}: {}>", request, target)));
+        }
+
+        let failed = |message: String| DependencyResolutionError::ResponseValue {
+            request: request.clone(),
+            target: target.to_string(),
+            message,
+        };
+        let outcome = Box::pin(self.execute_once(request))
+            .await
+            .map_err(|error| failed(error.to_string()))?;
+        if outcome.is_none() {
+            return Err(DependencyResolutionError::RequestSkipped {
+                request: request.clone(),
+            });
+        }
+
+        self.response_resolver
+            .lock()
+            .await
+            .resolve_value(request, target)
+            .map_err(|error| failed(error.to_string()))
     }
 
     async fn resolve_dependency_value(
-        &mut self,
+        &self,
         dep: &Dependency,
-        _placeholder: &str,
-    ) -> Result<String, DependencyResolutionError> {
+        placeholder: &str,
+    ) -> Result<Value, DependencyResolutionError> {
         match dep {
-            Dependency::EnvFile {
-                env_file,
-                key,
-                prompt,
-            } => {
-                // Load the TOML file
-                let mut env_data = load_env_file(env_file).map_err(|error| {
-                    DependencyResolutionError::NotImplemented(error.to_string())
-                })?;
-
-                if let Some(value) = env_data.get(key) {
-                    Ok(value.clone())
-                } else if let Some(prompt) = prompt {
-                    // Prompt the user
-                    let value = self.prompt_resolver.resolve(prompt.clone())?;
-                    // Optionally, save the value back to the env file or cache
-                    env_data.insert(key.clone(), value.clone());
-                    // Save back to the file
-                    save_env_file(env_file, &env_data).map_err(|error| {
-                        DependencyResolutionError::NotImplemented(error.to_string())
-                    })?;
-                    // Update the cache
-                    let mut cache = ENV_FILES_CACHE.lock().unwrap();
-                    cache.insert(env_file.clone(), env_data);
-                    Ok(value)
-                } else {
-                    Err(DependencyResolutionError::NotImplemented(format!(
-                        "Could resolve {} key from env file {}",
-                        key, env_file
-                    )))
-                }
+            Dependency::Response { request, target } => {
+                self.resolve_response_value(request, target).await
             }
-            Dependency::EnvVar { name, prompt } => {
-                if let Ok(env_value) = self
-                    .env_var_resolver
-                    .resolve((name.to_owned(), prompt.clone()))
-                {
-                    Ok(env_value)
-                } else {
-                    Err(DependencyResolutionError::NotImplemented(format!(
-                        "Could resolve variable {} from env",
-                        name
-                    )))
+            // Outside of a request, a template can only use the environment
+            Dependency::Template { template } => Ok(Value::String(
+                Box::pin(self.resolve_placeholders(template, None, &Variables::new())).await?,
+            )),
+            // Resolvers may prompt, run commands or save what they were
+            // given, none of which a dry run should do, so only values that
+            // can be read without that are shown
+            Dependency::Resolver { source, arguments } if self.options.dry_run => Ok(self
+                .resolvers
+                .preview(source, arguments)
+                .await?
+                .unwrap_or_else(|| Value::String(format!("<{}: {}>", source, placeholder)))),
+            Dependency::Resolver { source, arguments } => {
+                Ok(self.resolvers.resolve(source, arguments).await?)
+            }
+        }
+    }
+
+    /// Resolve every resolver dependency a request uses at the same time, so
+    /// that slow sources don't wait on each other. Failures are left for the
+    /// placeholders to report, since they may have a default.
+    async fn prefetch_dependencies(&self, request: &Request) -> Result<(), TemplateError> {
+        let placeholders = request.placeholders()?;
+        let pending = request
+            .dependencies
+            .iter()
+            .flatten()
+            .filter(|(name, _)| placeholders.contains(name.as_str()))
+            .filter_map(|(name, dependency)| match dependency {
+                Dependency::Resolver { source, arguments } => Some(async move {
+                    // A dry run only previews, which never has side effects
+                    let result = if self.options.dry_run {
+                        self.resolvers.preview(source, arguments).await.map(drop)
+                    } else {
+                        self.resolvers.resolve(source, arguments).await.map(drop)
+                    };
+                    if let Err(error) = result {
+                        debug!("Prefetching `{}` failed: {}", name, error);
+                    }
+                }),
+                _ => None,
+            });
+
+        futures::future::join_all(pending).await;
+        Ok(())
+    }
+}
+
+/// Split the segments of a URL template into those before the query and
+/// those of each query parameter, looking for `?` and `&` in literal text
+fn split_query(segments: Vec<Segment>) -> (Vec<Segment>, Option<Vec<Vec<Segment>>>) {
+    let mut base = Vec::new();
+    let mut query: Option<Vec<Vec<Segment>>> = None;
+
+    for segment in segments {
+        let text = match (segment, &mut query) {
+            (Segment::Literal(text), _) => text,
+            (placeholder, Some(params)) => {
+                params.last_mut().unwrap().push(placeholder);
+                continue;
+            }
+            (placeholder, None) => {
+                base.push(placeholder);
+                continue;
+            }
+        };
+
+        let mut rest = text.as_str();
+        let params = match &mut query {
+            Some(params) => params,
+            None => match rest.split_once('?') {
+                Some((before, after)) => {
+                    if !before.is_empty() {
+                        base.push(Segment::Literal(before.to_string()));
+                    }
+                    rest = after;
+                    query.insert(vec![Vec::new()])
                 }
+                None => {
+                    base.push(Segment::Literal(text));
+                    continue;
+                }
+            },
+        };
+        for (index, piece) in rest.split('&').enumerate() {
+            if index > 0 {
+                params.push(Vec::new());
             }
-            Dependency::OnePassword { vault, item, field } => Ok(self
-                .one_password_resolver
-                .resolve((vault.clone(), item.clone(), field.clone()))?),
-            Dependency::File { path } => {
-                let file_content = std::fs::read_to_string(path).map_err(|error| {
-                    DependencyResolutionError::NotImplemented(error.to_string())
-                })?;
-                Ok(file_content.trim().to_string())
+            if !piece.is_empty() {
+                let param = params.last_mut().unwrap();
+                param.push(Segment::Literal(piece.to_string()));
             }
-            Dependency::Response { request, target } => {
-                // Check if the request is already resolved
-                if let Ok(value) = self
-                    .response_resolver
-                    .resolve((request.clone(), target.clone()))
-                {
-                    return Ok(value);
+        }
+    }
+
+    (base, query)
+}
+
+/// Whether an optional placeholder resolved to nothing
+fn is_nothing(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::String(string) => string.is_empty(),
+        _ => false,
+    }
+}
+
+/// The text a value is inserted as: strings as is, `null` as nothing and
+/// anything else as JSON
+fn value_to_text(value: Value) -> String {
+    match value {
+        Value::Null => "".to_string(),
+        Value::String(string) => string,
+        other => other.to_string(),
+    }
+}
+
+fn build_client(
+    connect_timeout_ms: Option<u64>,
+    follow_redirects: bool,
+) -> Result<Client, ExecutionError> {
+    let builder = Client::builder();
+    let builder = if follow_redirects {
+        builder
+    } else {
+        builder.redirect(Policy::none())
+    };
+    let builder = match connect_timeout_ms {
+        Some(connect_timeout_ms) => {
+            builder.connect_timeout(Duration::from_millis(connect_timeout_ms))
+        }
+        None => builder,
+    };
+
+    builder
+        .build()
+        .map_err(|error| ExecutionError::Unknown(error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use futures::stream::TryStreamExt;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// An executor for `collection`, run with the extra command line `args`
+    fn executor(collection: &str, args: &[&str]) -> Result<Executor, ExecutionError> {
+        let config: Config = toml::from_str(collection).unwrap();
+        let options = Options::parse_from(["glint", "collection.toml"].iter().chain(args));
+        Executor::new(config, options, Registry::default())
+    }
+
+    /// A local server that answers `/items` with three items and any other
+    /// path with that path as a JSON string, slowly enough for requests to
+    /// overlap (and more slowly for paths with `slow` in them). Paths with
+    /// `busy` in them answer 429 the first time, and paths with `job` in them
+    /// answer a job that completes on the third time. Returns its URL, the most
+    /// requests it had in flight at once and every path it was asked for.
+    async fn serve_items() -> (String, Arc<AtomicUsize>, Arc<Mutex<Vec<String>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let hits = Arc::new(Mutex::new(Vec::new()));
+
+        let (most_seen, hits_seen) = (most.clone(), hits.clone());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let (in_flight, most, hits) = (in_flight.clone(), most.clone(), hits.clone());
+                tokio::spawn(async move {
+                    let mut buffer = vec![0; 4096];
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    let request = String::from_utf8_lossy(&buffer[..read]).to_string();
+                    let path = request.split_whitespace().nth(1).unwrap_or("/");
+                    let hit = {
+                        let mut hits = hits.lock().unwrap();
+                        hits.push(path.to_string());
+                        hits.iter().filter(|hit| *hit == path).count()
+                    };
+
+                    let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(current, Ordering::SeqCst);
+                    let delay = if path.contains("slow") { 150 } else { 50 };
+                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                    let body = match path {
+                        "/items" => json!([
+                            {"id": 1, "owner": {"login": "ann"}},
+                            {"id": 2, "owner": {"login": "bob"}},
+                            {"id": 3, "owner": {"login": "cy"}},
+                        ]),
+                        path if path.contains("job") => {
+                            json!({"status": if hit < 3 { "running" } else { "completed" }})
+                        }
+                        path => json!(path),
+                    }
+                    .to_string();
+                    let status = if path.contains("busy") && hit == 1 {
+                        "429 Too Many Requests"
+                    } else {
+                        "200 OK"
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                });
+            }
+        });
+
+        (url, most_seen, hits_seen)
+    }
+
+    fn foreach_collection(url: &str, names: &[&str]) -> String {
+        let mut collection = format!(
+            r#"
+            [[requests]]
+            name = "List"
+            method = "GET"
+            url = "{url}/items"
+            "#
+        );
+        for name in names {
+            collection.push_str(&format!(
+                r#"
+                [[requests]]
+                name = "{name}"
+                method = "GET"
+                url = "{url}/{name}/{{item.id}}/{{item.owner.login}}"
+                [requests.foreach]
+                request = "List"
+                target = {{ source = "JsonBody", pointer = "" }}
+                "#
+            ));
+        }
+        collection
+    }
+
+    #[tokio::test]
+    async fn test_foreach_runs_once_per_element() {
+        let (url, _, _) = serve_items().await;
+        let executor = executor(&foreach_collection(&url, &["owners"]), &["-j", "4"]).unwrap();
+
+        let responses = executor.execute_once("owners").await.unwrap().unwrap();
+        let texts: Vec<&str> = responses.iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                r#""/owners/1/ann""#,
+                r#""/owners/2/bob""#,
+                r#""/owners/3/cy""#
+            ]
+        );
+
+        // Every iteration is kept, and later requests see the last one
+        let history = executor.response_resolver.lock().await;
+        assert_eq!(history.latest("owners").unwrap().text, texts[2]);
+    }
+
+    #[tokio::test]
+    async fn test_independent_requests_run_concurrently_in_order() {
+        let (url, most, _) = serve_items().await;
+        let names = ["slow", "a", "b", "c"];
+        let collection: String = names
+            .iter()
+            .map(|name| {
+                format!(
+                    "[[requests]]\nname = \"{name}\"\nmethod = \"GET\"\nurl = \"{url}/{name}\"\n"
+                )
+            })
+            .collect();
+        let executor = executor(&collection, &["-j", "2"]).unwrap();
+
+        let order: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let outcomes: Vec<(&str, Outcome)> = executor
+            .execute_in_order(&order)
+            .try_collect()
+            .await
+            .unwrap();
+
+        // The slow request finishes last but still comes first
+        let texts: Vec<String> = outcomes
+            .iter()
+            .map(|(name, outcome)| format!("{}: {}", name, outcome.as_ref().unwrap()[0].text))
+            .collect();
+        assert_eq!(
+            texts,
+            [r#"slow: "/slow""#, r#"a: "/a""#, r#"b: "/b""#, r#"c: "/c""#]
+        );
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_jobs_limit_foreach_iterations_too() {
+        let (url, most, _) = serve_items().await;
+        let executor = executor(
+            &foreach_collection(&url, &["first", "second"]),
+            &["-j", "2", "-b"],
+        )
+        .unwrap();
+
+        executor.execute().await.unwrap();
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retry_waits_do_not_hold_a_job_slot() {
+        let (url, _, hits) = serve_items().await;
+        let mut collection = foreach_collection(&url, &["busy"]);
+        collection
+            .push_str("[requests.retry]\nmax_attempts = 2\nbackoff = \"Fixed\"\ndelay_ms = 300\n");
+        let executor = executor(&collection, &["-j", "1"]).unwrap();
+
+        let responses = executor.execute_once("busy").await.unwrap().unwrap();
+        assert!(responses.iter().all(|response| response.status == 200));
+
+        // Every element is sent while the first one waits for its retry
+        let hits = hits.lock().unwrap();
+        assert_eq!(
+            hits[..4],
+            ["/items", "/busy/1/ann", "/busy/2/bob", "/busy/3/cy"]
+        );
+        assert_eq!(hits.len(), 7);
+    }
+
+    fn poll_collection(url: &str, path: &str, poll: &str) -> String {
+        format!(
+            r#"
+            [[requests]]
+            name = "Get Job"
+            method = "GET"
+            url = "{url}{path}"
+            [requests.poll]
+            until = 'json("/status") == "completed"'
+            interval_ms = 10
+            {poll}
+            "#
+        )
+    }
+
+    #[tokio::test]
+    async fn test_polling_repeats_until_the_condition_holds() {
+        let (url, _, hits) = serve_items().await;
+        let executor = executor(&poll_collection(&url, "/job", ""), &[]).unwrap();
+
+        let responses = executor.execute_once("Get Job").await.unwrap().unwrap();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].text, r#"{"status":"completed"}"#);
+        assert_eq!(hits.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_polling_gives_up() {
+        let (url, _, hits) = serve_items().await;
+        let collection = poll_collection(&url, "/job/limited", "max_attempts = 2")
+            + &poll_collection(&url, "/job/late", "timeout_ms = 30").replace("Get Job", "Late");
+        let polling = executor(&collection, &[]).unwrap();
+
+        for (name, attempts) in [("Get Job", 2), ("Late", 1)] {
+            let request = polling.find_request(name).unwrap();
+            match polling.execute_request(request, &Variables::new()).await {
+                Err(ExecutionError::PollTimedOut {
+                    request,
+                    attempts: actual,
+                    status,
+                    ..
+                }) => {
+                    assert_eq!(request, name);
+                    assert_eq!(actual, attempts);
+                    assert_eq!(status, 200);
                 }
+                other => panic!("expected {} to time out, got {:?}", name, other),
+            }
+        }
+        assert_eq!(
+            *hits.lock().unwrap(),
+            ["/job/limited", "/job/limited", "/job/late"]
+        );
+    }
 
-                let cloned_request = self
-                    .requests
-                    .get(request)
-                    .ok_or(DependencyResolutionError::NotImplemented(format!(
-                        "Request configuration for {} could not be found",
-                        request
-                    )))?
-                    .clone();
+    #[tokio::test]
+    async fn test_text_bodies_keep_their_own_braces() {
+        let executor = executor("requests = []", &[]).unwrap();
+        let mut request: Request =
+            toml::from_str("name = \"Create\"\nmethod = \"POST\"\nurl = \"https://example.com\"")
+                .unwrap();
+        request.body = Some(RequestBody::Text(
+            r#"{"a":{"b":{id}}} query { viewer { login } }"#.to_string(),
+        ));
+        let variables = Variables::from([("id".to_string(), json!(5))]);
 
-                Box::pin(self.execute_request(cloned_request))
-                    .await
-                    .map_err(|error| {
-                        DependencyResolutionError::NotImplemented(error.to_string())
-                    })?;
+        let prepared = executor
+            .prepare_request(&request, &variables)
+            .await
+            .unwrap();
+        match prepared.body {
+            Some(RequestBody::Text(text)) => {
+                assert_eq!(text, r#"{"a":{"b":5}} query { viewer { login } }"#)
+            }
+            other => panic!("expected a text body, got {:?}", other),
+        }
+    }
 
-                Ok(self
-                    .response_resolver
-                    .resolve((request.to_owned(), target.to_owned()))
-                    .map_err(|error| {
-                        DependencyResolutionError::NotImplemented(error.to_string())
-                    })?)
+    #[test]
+    fn test_dry_runs_mask_urls_and_text_bodies() {
+        let request: Request = toml::from_str(
+            r#"
+            name = "Login"
+            method = "POST"
+            url = "https://example.com/login?token=abc123"
+            [[masking_rules]]
+            path = "$"
+            regex = "token=\\w+"
+            replace = "token=***"
+            "#,
+        )
+        .unwrap();
+        let text = "token=abc123&user=ann";
+
+        let masking = executor("requests = []", &["--dry-run"]).unwrap();
+        assert_eq!(
+            masking.masked(&request.url, &request.masking_rules),
+            "https://example.com/login?token=***"
+        );
+        assert_eq!(
+            masking.masked(text, &request.masking_rules),
+            "token=***&user=ann"
+        );
+
+        let unmasked = executor("requests = []", &["--dry-run", "-m"]).unwrap();
+        assert_eq!(unmasked.masked(text, &request.masking_rules), text);
+    }
+
+    #[tokio::test]
+    async fn test_missing_element_fields_are_reported() {
+        let executor = executor("requests = []", &[]).unwrap();
+        let variables = Variables::from([("item".to_string(), json!({"tags": ["a"]}))]);
+
+        assert_eq!(
+            executor
+                .resolve_placeholders("/tags/{item.tags.0}", None, &variables)
+                .await
+                .unwrap(),
+            "/tags/a"
+        );
+        for placeholder in ["{item.tags.1}", "{item.owner.login}"] {
+            assert!(matches!(
+                executor
+                    .resolve_placeholders(placeholder, None, &variables)
+                    .await,
+                Err(DependencyResolutionError::PlaceholderDefinitionNotFound { .. })
+            ));
+        }
+    }
+
+    const TIMEOUTS: &str = r#"
+        [settings]
+        timeout_ms = 5000
+        connect_timeout_ms = 1000
+
+        [[requests]]
+        name = "Default"
+        method = "GET"
+        url = "https://example.com"
+
+        [[requests]]
+        name = "Quick"
+        method = "GET"
+        url = "https://example.com"
+        timeout_ms = 200
+        connect_timeout_ms = 300
+
+        [[requests]]
+        name = "Redirect"
+        method = "GET"
+        url = "https://example.com"
+        follow_redirects = false
+    "#;
+
+    #[test]
+    fn test_timeouts_combine_flag_request_and_settings() {
+        let timeouts = |args: &[&str]| {
+            let executor = executor(TIMEOUTS, args).unwrap();
+            ["Default", "Quick"]
+                .map(|name| executor.timeout_for(executor.find_request(name).unwrap()))
+        };
+        assert_eq!(
+            timeouts(&[]),
+            [
+                Some(Duration::from_millis(5000)),
+                Some(Duration::from_millis(200))
+            ]
+        );
+        // The flag overrides both
+        assert_eq!(
+            timeouts(&["--timeout", "50"]),
+            [
+                Some(Duration::from_millis(50)),
+                Some(Duration::from_millis(50))
+            ]
+        );
+
+        let request: Request =
+            toml::from_str("name = \"Any\"\nmethod = \"GET\"\nurl = \"/\"").unwrap();
+        let executor = executor("requests = []", &[]).unwrap();
+        assert_eq!(executor.timeout_for(&request), None);
+    }
+
+    #[test]
+    fn test_clients_are_built_for_connect_timeouts_and_redirects() {
+        let executor = executor(TIMEOUTS, &[]).unwrap();
+        for name in ["Default", "Quick", "Redirect"] {
+            executor
+                .client_for(executor.find_request(name).unwrap())
+                .unwrap();
+        }
+
+        // The default client already has the collection's connect timeout,
+        // and a request that doesn't follow redirects still inherits it
+        let clients = executor.clients.lock().unwrap();
+        let mut keys: Vec<_> = clients.keys().copied().collect();
+        keys.sort();
+        assert_eq!(keys, [(Some(300), true), (Some(1000), false)]);
+    }
+
+    #[tokio::test]
+    async fn test_timeouts_are_reported_as_such() {
+        let (url, _, _) = serve_items().await;
+        let executor = executor(
+            &format!(
+                r#"
+                [[requests]]
+                name = "Slow"
+                method = "GET"
+                url = "{url}/slow"
+                timeout_ms = 10
+                "#
+            ),
+            &[],
+        )
+        .unwrap();
+
+        let request = executor.find_request("Slow").unwrap();
+        match executor.execute_request(request, &Variables::new()).await {
+            Err(ExecutionError::Timeout { request, timeout }) => {
+                assert_eq!(request, "Slow");
+                assert_eq!(timeout, Duration::from_millis(10));
             }
-            Dependency::Prompt { label } => Ok(self.prompt_resolver.resolve(label.clone())?),
+            other => panic!("expected a timeout, got {:?}", other),
         }
     }
-}
 
-fn load_env_file(env_file: &str) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
-    let mut cache = ENV_FILES_CACHE.lock().unwrap();
+    const ENVIRONMENTS: &str = r#"
+        requests = []
 
-    if let Some(data) = cache.get(env_file) {
-        Ok(data.clone())
-    } else {
-        // Read and parse the TOML file
-        let content = std::fs::read_to_string(env_file)?;
-        let data: HashMap<String, String> = toml::from_str(&content)?;
-        cache.insert(env_file.to_string(), data.clone());
-        Ok(data)
+        [environments.dev]
+        env_file = "missing.toml"
+        [environments.dev.variables]
+        api_url = "http://localhost:3000"
+        env_file = "a variable like any other"
+
+        [environments.prod.variables]
+        api_url = "https://api.example.com"
+    "#;
+
+    #[tokio::test]
+    async fn test_env_selects_an_environment() {
+        for (env, api_url) in [
+            ("dev", "http://localhost:3000"),
+            ("prod", "https://api.example.com"),
+        ] {
+            let executor = executor(ENVIRONMENTS, &["--env", env]).unwrap();
+            let resolved = executor
+                .resolve_placeholders("{api_url}", None, &Variables::new())
+                .await
+                .unwrap();
+            assert_eq!(resolved, api_url);
+        }
+
+        let dev = executor(ENVIRONMENTS, &["--env", "dev"]).unwrap();
+        let resolved = dev
+            .resolve_placeholders("{env_file}", None, &Variables::new())
+            .await
+            .unwrap();
+        assert_eq!(resolved, "a variable like any other");
+
+        // Without --env, environments aren't consulted
+        let executor = executor(ENVIRONMENTS, &[]).unwrap();
+        assert!(matches!(
+            executor
+                .resolve_placeholders("{api_url}", None, &Variables::new())
+                .await,
+            Err(DependencyResolutionError::PlaceholderDefinitionNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_unknown_environments_are_reported() {
+        match executor(ENVIRONMENTS, &["--env", "staging"]) {
+            Err(ExecutionError::EnvironmentNotFound { environment }) => {
+                assert_eq!(environment, "staging")
+            }
+            other => panic!("expected an unknown environment, got {:?}", other.err()),
+        }
+
+        // Variables outside of their table are a mistake, not ignored
+        let misplaced = "requests = []\n[environments.dev]\napi_url = \"http://localhost\"";
+        assert!(toml::from_str::<Config>(misplaced).is_err());
+    }
+
+    #[derive(Debug)]
+    struct Forbidden;
+
+    #[async_trait::async_trait]
+    impl crate::resolvers::Resolver for Forbidden {
+        async fn resolve(
+            &self,
+            _arguments: &crate::resolvers::Arguments,
+        ) -> Result<Value, ResolverError> {
+            panic!("a dry run must not run resolvers")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dry_runs_stand_in_for_resolvers() {
+        // Sources without side effects are still read
+        let host = std::env::temp_dir().join(format!("glint-dry-run-{}", std::process::id()));
+        std::fs::write(&host, "api.example.com\n").unwrap();
+        let config: Config = toml::from_str(&format!(
+            r#"
+            [[requests]]
+            name = "Get User"
+            method = "GET"
+            url = "https://{{host}}/users?token={{token}}"
+            [requests.dependencies.token]
+            source = "Secret"
+            key = "api_token"
+            [requests.dependencies.host]
+            source = "File"
+            path = "{}"
+            "#,
+            host.display()
+        ))
+        .unwrap();
+        let mut resolvers = Registry::default();
+        resolvers.register("Secret", Forbidden);
+        resolvers.register("File", crate::resolvers::file_resolver::FileResolver);
+        let executor = Executor::new(
+            config,
+            Options::parse_from(["glint", "collection.toml", "--dry-run"]),
+            resolvers,
+        )
+        .unwrap();
+
+        let request = executor.find_request("Get User").unwrap();
+        let prepared = executor
+            .prepare_request(request, &Variables::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            prepared.url,
+            "https://api.example.com/users?token=<Secret: token>"
+        );
+        std::fs::remove_file(&host).unwrap();
     }
-}
 
-fn save_env_file(
-    env_file: &str,
-    data: &HashMap<String, String>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let content = toml::to_string(data)?;
-    std::fs::write(env_file, content)?;
-    Ok(())
+    #[tokio::test]
+    async fn test_bodies_that_are_not_json_are_printed_as_text() {
+        let executor = executor("requests = []", &["--raw-output"]).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "text/html".parse().unwrap());
+        let response = Response::fixture(
+            r#"
+            name = "Login Page"
+            method = "GET"
+            url = "https://example.com/login"
+            [[masking_rules]]
+            path = "$"
+            regex = "csrf=\\w+"
+            replace = "csrf=***"
+            "#,
+            200,
+            headers,
+            "<a href='/?csrf=abc123'>",
+        );
+
+        assert_eq!(
+            mask_text(&response.text, &response.request.masking_rules),
+            "<a href='/?csrf=***'>"
+        );
+        assert!(executor.render_output(response).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_json_placeholders_keep_their_type() {
+        let config: Config = toml::from_str("requests = []").unwrap();
+        let executor = Executor::new(
+            config,
+            Options::parse_from(["glint", "collection.toml"]),
+            Registry::default(),
+        )
+        .unwrap();
+        let variables = Variables::from([(
+            "item".to_string(),
+            json!({"id": 7, "tags": ["a", "b"], "name": "say \"hi\""}),
+        )]);
+
+        let resolved = executor
+            .resolve_json(
+                &json!({
+                    "id": "{item.id}",
+                    "tags": "{item.tags}",
+                    "label": "{item.name} (#{item.id})",
+                    "nested": [{"{item.tags.0}": "{item.name | upper}"}],
+                    "count": 3,
+                }),
+                None,
+                &variables,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            resolved,
+            Some(json!({
+                "id": 7,
+                "tags": ["a", "b"],
+                "label": "say \"hi\" (#7)",
+                "nested": [{"a": "SAY \"HI\""}],
+                "count": 3,
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_defaults_and_optional_placeholders() {
+        let config: Config = toml::from_str("requests = []").unwrap();
+        let executor = Executor::new(
+            config,
+            Options::parse_from(["glint", "collection.toml"]),
+            Registry::default(),
+        )
+        .unwrap();
+        let variables = Variables::from([
+            ("page".to_string(), json!(2)),
+            ("empty".to_string(), json!("")),
+        ]);
+
+        let url = executor
+            .resolve_url(
+                "https://example.com/items?page={page}&q={query?}&sort={sort:-name | upper}",
+                None,
+                &variables,
+            )
+            .await
+            .unwrap();
+        assert_eq!(url, "https://example.com/items?page=2&sort=NAME");
+
+        // `?` and `&` inside placeholders don't split the query
+        for (template, expected) in [
+            (
+                "https://example.com/users/{id?}/items?x=1",
+                "https://example.com/users//items?x=1",
+            ),
+            (
+                "https://example.com/items?sort={sort:-a?b&c}&q={query?}",
+                "https://example.com/items?sort=a?b&c",
+            ),
+            (
+                "https://example.com/{query?}?q={query?}",
+                "https://example.com/",
+            ),
+        ] {
+            let url = executor
+                .resolve_url(template, None, &variables)
+                .await
+                .unwrap();
+            assert_eq!(url, expected);
+        }
+
+        let json = executor
+            .resolve_json(
+                &json!({"page": "{page:-1}", "q": "{empty?}", "tags": ["{query?}", "a"]}),
+                None,
+                &variables,
+            )
+            .await
+            .unwrap();
+        assert_eq!(json, Some(json!({"page": 2, "tags": ["a"]})));
+
+        assert!(executor
+            .resolve_placeholders("{query}", None, &variables)
+            .await
+            .is_err());
+    }
 }
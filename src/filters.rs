@@ -0,0 +1,56 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FilterError {
+    #[error("Unknown filter `{filter}` in `{placeholder}` (expected one of: {})", FILTERS.join(", "))]
+    UnknownFilter { filter: String, placeholder: String },
+}
+
+const FILTERS: [&str; 6] = ["base64", "urlencode", "upper", "lower", "trim", "sha256"];
+
+/// Apply a filter from a placeholder's pipeline, such as the `base64` in
+/// `{credentials | base64}`
+pub fn apply(filter: &str, value: String, placeholder: &str) -> Result<String, FilterError> {
+    Ok(match filter {
+        "base64" => STANDARD.encode(value),
+        "urlencode" => urlencoding::encode(&value).into_owned(),
+        "upper" => value.to_uppercase(),
+        "lower" => value.to_lowercase(),
+        "trim" => value.trim().to_string(),
+        "sha256" => hex::encode(Sha256::digest(value)),
+        _ => {
+            return Err(FilterError::UnknownFilter {
+                filter: filter.to_string(),
+                placeholder: placeholder.to_string(),
+            })
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters() {
+        let apply = |filter, value: &str| apply(filter, value.to_string(), "{x}").unwrap();
+
+        assert_eq!(apply("base64", "id:secret"), "aWQ6c2VjcmV0");
+        assert_eq!(apply("urlencode", "a b&c"), "a%20b%26c");
+        assert_eq!(apply("upper", "Ada"), "ADA");
+        assert_eq!(apply("lower", "Ada"), "ada");
+        assert_eq!(apply("trim", "  7 \n"), "7");
+        assert_eq!(
+            apply("sha256", "abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let error = super::apply("rot13", "x".to_string(), "{x | rot13}").unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Unknown filter `rot13` in `{x | rot13}`"));
+    }
+}
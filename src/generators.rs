@@ -0,0 +1,160 @@
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use lazy_static::lazy_static;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use regex::Regex;
+use thiserror::Error;
+use tracing::debug;
+
+lazy_static! {
+    /// `$name`, with optional arguments and an optional offset for dates,
+    /// e.g. `$randomInt(1, 100)` or `$now + 1d`
+    static ref GENERATOR_REGEX: Regex = Regex::new(
+        r"^\$(\w+)(?:\(([^)]*)\))?(?:\s*([+-])\s*(\d+)\s*([smhdw]))?$"
+    )
+    .unwrap();
+}
+
+#[derive(Debug, Error)]
+pub enum GeneratorError {
+    #[error("Unknown generator `{generator}` (expected $uuid, $timestamp, $isoDate, $now, $randomInt or $randomString)")]
+    UnknownGenerator { generator: String },
+    #[error("Invalid generator `{generator}`: {message}")]
+    InvalidGenerator { generator: String, message: String },
+}
+
+/// Generate a fresh value for a built-in placeholder like `{$uuid}`. Every
+/// occurrence gets its own value.
+pub fn generate(generator: &str) -> Result<String, GeneratorError> {
+    let invalid = |message: &str| GeneratorError::InvalidGenerator {
+        generator: generator.to_string(),
+        message: message.to_string(),
+    };
+
+    let caps = GENERATOR_REGEX
+        .captures(generator)
+        .ok_or_else(|| invalid("expected `$name`, `$name(arguments)` or `$name + offset`"))?;
+    let name = &caps[1];
+    let arguments: Vec<&str> = caps
+        .get(2)
+        .map(|arguments| arguments.as_str().split(',').map(str::trim).collect())
+        .unwrap_or_default();
+    let offset = match (caps.get(3), caps.get(4), caps.get(5)) {
+        (Some(sign), Some(amount), Some(unit)) => {
+            let amount: i64 = amount
+                .as_str()
+                .parse()
+                .map_err(|_| invalid("offset is too large"))?;
+            let amount = if sign.as_str() == "-" {
+                -amount
+            } else {
+                amount
+            };
+            let offset = match unit.as_str() {
+                "s" => Duration::try_seconds(amount),
+                "m" => Duration::try_minutes(amount),
+                "h" => Duration::try_hours(amount),
+                "d" => Duration::try_days(amount),
+                _ => Duration::try_weeks(amount),
+            };
+            Some(offset.ok_or_else(|| invalid("offset is too large"))?)
+        }
+        _ => None,
+    };
+
+    let now = || -> Result<DateTime<Utc>, GeneratorError> {
+        Utc::now()
+            .checked_add_signed(offset.unwrap_or_default())
+            .ok_or_else(|| invalid("offset is out of range"))
+    };
+    let no_offset = || match offset {
+        Some(_) => Err(invalid("only dates can be offset")),
+        None => Ok(()),
+    };
+
+    let value = match (name, arguments.as_slice()) {
+        ("now", []) => now()?.to_rfc3339_opts(SecondsFormat::Secs, true),
+        ("timestamp", []) => now()?.timestamp().to_string(),
+        ("isoDate", []) => now()?.format("%Y-%m-%d").to_string(),
+        ("uuid", []) => {
+            no_offset()?;
+            uuid::Uuid::new_v4().to_string()
+        }
+        ("randomInt", [min, max]) => {
+            no_offset()?;
+            let min: i64 = min.parse().map_err(|_| invalid("min is not an integer"))?;
+            let max: i64 = max.parse().map_err(|_| invalid("max is not an integer"))?;
+            if min > max {
+                return Err(invalid("min is greater than max"));
+            }
+            rand::thread_rng().gen_range(min..=max).to_string()
+        }
+        ("randomString", [length]) => {
+            no_offset()?;
+            let length: usize = length
+                .parse()
+                .map_err(|_| invalid("length is not a number"))?;
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(length)
+                .map(char::from)
+                .collect()
+        }
+        ("now" | "timestamp" | "isoDate" | "uuid", _) => return Err(invalid("takes no arguments")),
+        ("randomInt", _) => return Err(invalid("expected $randomInt(min, max)")),
+        ("randomString", _) => return Err(invalid("expected $randomString(length)")),
+        _ => {
+            return Err(GeneratorError::UnknownGenerator {
+                generator: generator.to_string(),
+            })
+        }
+    };
+
+    debug!(generator, value, "Generated value");
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_generators() {
+        assert_ne!(generate("$uuid").unwrap(), generate("$uuid").unwrap());
+        assert_eq!(generate("$uuid").unwrap().len(), 36);
+
+        let number: i64 = generate("$randomInt(5, 7)").unwrap().parse().unwrap();
+        assert!((5..=7).contains(&number));
+
+        let string = generate("$randomString(12)").unwrap();
+        assert_eq!(string.len(), 12);
+        assert!(string.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        let today = NaiveDate::parse_from_str(&generate("$isoDate").unwrap(), "%Y-%m-%d").unwrap();
+        let tomorrow =
+            NaiveDate::parse_from_str(&generate("$isoDate + 1d").unwrap(), "%Y-%m-%d").unwrap();
+        assert_eq!(tomorrow - today, Duration::days(1));
+
+        let now: i64 = generate("$timestamp").unwrap().parse().unwrap();
+        let later: i64 = generate("$timestamp + 2h").unwrap().parse().unwrap();
+        assert!((later - now - 7200).abs() <= 1);
+        assert!(DateTime::parse_from_rfc3339(&generate("$now - 1w").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_generators() {
+        assert!(matches!(
+            generate("$guid"),
+            Err(GeneratorError::UnknownGenerator { .. })
+        ));
+        assert!(matches!(
+            generate("$randomInt(9, 1)"),
+            Err(GeneratorError::InvalidGenerator { .. })
+        ));
+        assert!(matches!(
+            generate("$uuid + 1d"),
+            Err(GeneratorError::InvalidGenerator { .. })
+        ));
+    }
+}
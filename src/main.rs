@@ -1,24 +1,58 @@
+mod assertions;
+mod bench;
+mod condition;
+mod env_file;
 mod executor;
+mod filters;
+mod generators;
 mod logging;
 mod masking;
 mod options;
+mod pagination;
+mod planner;
+mod poll;
+mod private_file;
 mod request;
 mod resolvers;
 mod response;
+mod retry;
+mod template;
+mod vault;
 use clap::Parser;
 use executor::Executor;
 use logging::init_logging;
-use options::Options;
+use options::{Command, Options};
+use resolvers::Registry;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let guard = init_logging()?;
 
-    let options = Options::parse();
+    let mut options = Options::parse();
 
-    let requests = request::load_requests_from_toml(options.collection.as_str())?;
+    let vault_path = options.vault.clone().unwrap_or_else(vault::default_path);
 
-    Executor::new(requests, options).execute().await?;
+    match options.command.take() {
+        Some(Command::Bench(bench_options)) => {
+            let config = request::load_config_from_toml(&bench_options.collection)?;
+            let raw_output = options.raw_output;
+            let executor = Executor::new(config, options, Registry::with_defaults(vault_path))?;
+
+            bench::run(&executor, &bench_options, raw_output).await?;
+        }
+        Some(Command::Vault(vault_options)) => vault::run(&vault_options, &vault_path)?,
+        None => {
+            let collection = options
+                .collection
+                .clone()
+                .expect("a collection is required without a subcommand");
+            let config = request::load_config_from_toml(&collection)?;
+
+            Executor::new(config, options, Registry::with_defaults(vault_path))?
+                .execute()
+                .await?;
+        }
+    }
 
     drop(guard);
 
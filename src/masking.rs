@@ -68,6 +68,17 @@ pub fn mask_json(json: Value, rules: &[MaskingRule]) -> Result<Value, MaskingErr
     Ok(new_json)
 }
 
+/// Mask a body that isn't JSON, such as an HTML page. Only rules for the
+/// root path (`$`) apply, to the whole text.
+pub fn mask_text(text: &str, rules: &[MaskingRule]) -> String {
+    rules
+        .iter()
+        .filter(|rule| rule.path == "$")
+        .fold(text.to_string(), |text, rule| {
+            rule.regex.0.replace_all(&text, &rule.replace).to_string()
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,10 +1,22 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    disable_help_flag = true,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct Options {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// The collection file to use
-    pub collection: String,
+    #[arg(required = true)]
+    pub collection: Option<String>,
 
     /// The specific request to execute within the collection (optional)
     pub request: Option<String>,
@@ -22,10 +34,134 @@ pub struct Options {
     pub hide_body: bool,
 
     /// Disables pretty-printing for the HTTP response (enabled by default)
-    #[arg(short = 'r', long, default_value_t = false)]
+    #[arg(short = 'r', long, default_value_t = false, global = true)]
     pub raw_output: bool,
 
     /// Disables pre-output masking (enabled by default)
     #[arg(short = 'm', long, default_value_t = false)]
     pub disable_masking: bool,
+
+    /// The environment to resolve placeholders from, as declared under `[environments]`
+    #[arg(short = 'e', long, global = true)]
+    pub env: Option<String>,
+
+    /// Maximum time in milliseconds to wait for each request, overriding the collection
+    #[arg(short = 't', long, value_name = "MS", global = true)]
+    pub timeout: Option<u64>,
+
+    /// Number of independent requests to send at the same time
+    #[arg(short = 'j', long, default_value_t = 1)]
+    pub jobs: usize,
+
+    /// Prints the execution order without sending any requests
+    #[arg(short = 'p', long, default_value_t = false)]
+    pub plan: bool,
+
+    /// Prints each request with its placeholders resolved, without sending it
+    #[arg(short = 'n', long, default_value_t = false)]
+    pub dry_run: bool,
+
+    /// The vault file for `Vault` dependencies and `glint vault` (~/.glint/vault by default)
+    #[arg(long, value_name = "PATH", env = "GLINT_VAULT", global = true)]
+    pub vault: Option<PathBuf>,
+
+    /// Print help
+    #[arg(long, action = clap::ArgAction::Help, global = true)]
+    help: Option<bool>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Sends a request repeatedly and reports throughput and latency
+    Bench(BenchOptions),
+    /// Manages the encrypted credential vault
+    Vault(VaultOptions),
+}
+
+#[derive(Debug, Args)]
+pub struct BenchOptions {
+    /// The collection file to use
+    pub collection: String,
+
+    /// The request to send
+    pub request: String,
+
+    /// Number of requests to send (100 unless a duration is given)
+    #[arg(short = 'c', long)]
+    pub count: Option<usize>,
+
+    /// Keep sending requests for this many seconds
+    #[arg(short = 'd', long, value_name = "SECONDS")]
+    pub duration: Option<u64>,
+
+    /// Number of requests in flight at the same time
+    #[arg(short = 'j', long, default_value_t = 1)]
+    pub concurrency: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct VaultOptions {
+    #[command(subcommand)]
+    pub command: VaultCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum VaultCommand {
+    /// Creates a new vault, protected by a passphrase
+    Init,
+    /// Stores a value, read from standard input or asked for without echoing
+    /// it, so it never appears in the command line
+    Set { key: String },
+    /// Prints a value
+    Get { key: String },
+    /// Lists the stored keys
+    List,
+    /// Removes a value
+    Rm { key: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_options_are_well_formed() {
+        Options::command().debug_assert();
+    }
+
+    #[test]
+    fn test_bench_is_a_subcommand() {
+        let options = Options::parse_from([
+            "glint", "bench", "api.toml", "Get User", "-c", "50", "-e", "dev",
+        ]);
+        assert!(options.collection.is_none());
+        assert_eq!(options.env.as_deref(), Some("dev"));
+        match options.command {
+            Some(Command::Bench(bench)) => {
+                assert_eq!(bench.request, "Get User");
+                assert_eq!(bench.count, Some(50));
+            }
+            _ => panic!("expected the bench subcommand"),
+        }
+
+        let options = Options::parse_from(["glint", "api.toml", "Get User"]);
+        assert!(options.command.is_none());
+        assert_eq!(options.collection.as_deref(), Some("api.toml"));
+    }
+
+    #[test]
+    fn test_vault_subcommands() {
+        let options = Options::parse_from(["glint", "vault", "set", "token", "--vault", "v.json"]);
+        assert_eq!(options.vault, Some(PathBuf::from("v.json")));
+        match options.command {
+            Some(Command::Vault(VaultOptions {
+                command: VaultCommand::Set { key },
+            })) => assert_eq!(key, "token"),
+            _ => panic!("expected the vault set subcommand"),
+        }
+
+        // Values can't be passed as arguments, where `ps` would show them
+        assert!(Options::try_parse_from(["glint", "vault", "set", "token", "secret"]).is_err());
+    }
 }
@@ -0,0 +1,356 @@
+use crate::response::Response;
+use reqwest::header::{HeaderMap, LINK};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::debug;
+
+#[derive(Debug, Error)]
+pub enum PaginationError {
+    #[error("Invalid URL `{url:?}`: {message}")]
+    InvalidUrl { url: String, message: String },
+    #[error("Page {page} of request `{request:?}` is not JSON")]
+    NotJson { request: String, page: usize },
+    #[error("Page {page} of request `{request:?}` has no array of items at `{pointer}`")]
+    ItemsNotFound {
+        request: String,
+        page: usize,
+        pointer: String,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum PaginationStrategy {
+    /// Follow the `rel="next"` URL of the RFC 5988 `Link` header
+    Link,
+    /// Send the value at the JSON pointer `cursor` as the `param` query parameter
+    Cursor { cursor: String, param: String },
+    /// Count pages in the `param` query parameter, starting at `start`
+    Page {
+        param: String,
+        #[serde(default = "default_page_start")]
+        start: u64,
+    },
+    /// Count items in the `param` query parameter, starting at `start`
+    Offset {
+        param: String,
+        #[serde(default)]
+        start: u64,
+    },
+}
+
+fn default_page_start() -> u64 {
+    1
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub enum PaginationMode {
+    /// Merge the items of every page into a single JSON array
+    #[default]
+    Merge,
+    /// Keep one response per page
+    Pages,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Pagination {
+    #[serde(flatten)]
+    pub strategy: PaginationStrategy,
+    /// JSON pointer to the array of items in each page (the whole body by default)
+    #[serde(default)]
+    pub items: String,
+    #[serde(default = "default_max_pages")]
+    pub max_pages: usize,
+    #[serde(default)]
+    pub mode: PaginationMode,
+}
+
+fn default_max_pages() -> usize {
+    10
+}
+
+impl Pagination {
+    /// The URL of the first page
+    fn first_url(&self, url: &str) -> Result<String, PaginationError> {
+        match &self.strategy {
+            PaginationStrategy::Page { param, start }
+            | PaginationStrategy::Offset { param, start } => {
+                with_query_param(url, param, &start.to_string())
+            }
+            PaginationStrategy::Link | PaginationStrategy::Cursor { .. } => Ok(url.to_string()),
+        }
+    }
+
+    /// The URL of the page after `pages` (the first page when `pages` is
+    /// empty), or `None` once the last page has been fetched. A page that
+    /// wasn't successful is the last one.
+    pub fn next_url(
+        &self,
+        pages: &[Response],
+        url: &str,
+    ) -> Result<Option<String>, PaginationError> {
+        let Some(page) = pages.last() else {
+            return self.first_url(url).map(Some);
+        };
+        if !page.status.is_success() {
+            debug!("Page {} failed with {}", pages.len(), page.status);
+            return Ok(None);
+        }
+        if pages.len() >= self.max_pages {
+            debug!("Reached the limit of {} pages", self.max_pages);
+            return Ok(None);
+        }
+
+        let next_url = match &self.strategy {
+            PaginationStrategy::Link => match next_link(&page.headers) {
+                Some(next) => Some(
+                    Url::parse(url)
+                        .and_then(|base| base.join(&next))
+                        .map_err(|error| PaginationError::InvalidUrl {
+                            url: next.clone(),
+                            message: error.to_string(),
+                        })?
+                        .to_string(),
+                ),
+                None => None,
+            },
+            PaginationStrategy::Cursor { cursor, param } => {
+                let json = self.json(page, pages.len())?;
+                match json.pointer(cursor) {
+                    Some(Value::String(next)) if !next.is_empty() => {
+                        Some(with_query_param(url, param, next)?)
+                    }
+                    Some(Value::Number(next)) => {
+                        Some(with_query_param(url, param, &next.to_string())?)
+                    }
+                    _ => None,
+                }
+            }
+            PaginationStrategy::Page { param, start } => {
+                if self.page_items(page, pages.len())?.is_empty() {
+                    None
+                } else {
+                    let next = start + pages.len() as u64;
+                    Some(with_query_param(url, param, &next.to_string())?)
+                }
+            }
+            PaginationStrategy::Offset { param, start } => {
+                let mut offset = *start;
+                for (index, page) in pages.iter().enumerate() {
+                    offset += self.page_items(page, index + 1)?.len() as u64;
+                }
+                if self.page_items(page, pages.len())?.is_empty() {
+                    None
+                } else {
+                    Some(with_query_param(url, param, &offset.to_string())?)
+                }
+            }
+        };
+
+        debug!(?next_url, "Computed next page");
+        Ok(next_url)
+    }
+
+    /// Combine every page into a single response whose body is the array of
+    /// all their items, with the status and headers of the first page. If a
+    /// page wasn't successful, that page is returned as is instead, so the
+    /// failure isn't hidden behind the pages before it.
+    pub fn merge(&self, mut pages: Vec<Response>) -> Result<Response, PaginationError> {
+        if let Some(failed) = pages.iter().position(|page| !page.status.is_success()) {
+            return Ok(pages.swap_remove(failed));
+        }
+
+        let mut items = Vec::new();
+        for (index, page) in pages.iter().enumerate() {
+            items.extend(self.page_items(page, index + 1)?);
+        }
+
+        let elapsed = pages.iter().map(|page| page.elapsed).sum::<Duration>();
+        let mut merged = pages
+            .into_iter()
+            .next()
+            .expect("a paginated request fetches at least one page");
+        merged.text = Value::Array(items).to_string();
+        merged.elapsed = elapsed;
+
+        Ok(merged)
+    }
+
+    fn json(&self, page: &Response, number: usize) -> Result<Value, PaginationError> {
+        page.json().map_err(|_| PaginationError::NotJson {
+            request: page.request.name.clone(),
+            page: number,
+        })
+    }
+
+    fn page_items(&self, page: &Response, number: usize) -> Result<Vec<Value>, PaginationError> {
+        match self.json(page, number)?.pointer(&self.items) {
+            Some(Value::Array(items)) => Ok(items.clone()),
+            _ => Err(PaginationError::ItemsNotFound {
+                request: page.request.name.clone(),
+                page: number,
+                pointer: self.items.clone(),
+            }),
+        }
+    }
+}
+
+/// The `rel="next"` URL of an RFC 5988 `Link` header, if there is one
+fn next_link(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|link| {
+            let mut parts = link.split(';');
+            let target = parts.next()?.trim();
+            let is_next = parts.any(|param| {
+                let param = param.trim().to_ascii_lowercase();
+                param == "rel=\"next\"" || param == "rel=next"
+            });
+            is_next.then(|| {
+                target
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string()
+            })
+        })
+}
+
+/// Set a query parameter, replacing any existing value
+fn with_query_param(url: &str, param: &str, value: &str) -> Result<String, PaginationError> {
+    let mut parsed = Url::parse(url).map_err(|error| PaginationError::InvalidUrl {
+        url: url.to_string(),
+        message: error.to_string(),
+    })?;
+    let pairs: Vec<(String, String)> = parsed
+        .query_pairs()
+        .filter(|(key, _)| key != param)
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+
+    parsed
+        .query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair(param, value);
+
+    Ok(parsed.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(body: &str, link: Option<&str>) -> Response {
+        page_with_status(200, body, link)
+    }
+
+    fn page_with_status(status: u16, body: &str, link: Option<&str>) -> Response {
+        let mut headers = HeaderMap::new();
+        if let Some(link) = link {
+            headers.insert(LINK, link.parse().unwrap());
+        }
+
+        Response::fixture(
+            r#"
+            name = "List"
+            method = "GET"
+            url = "https://example.com/items"
+            "#,
+            status,
+            headers,
+            body,
+        )
+    }
+
+    #[test]
+    fn test_link_pagination_follows_next() {
+        let pagination: Pagination = toml::from_str(r#"type = "Link""#).unwrap();
+        let url = "https://example.com/items";
+        let link = r#"<https://example.com/items?page=1>; rel="prev", </items?page=3>; rel="next""#;
+
+        assert_eq!(
+            pagination.next_url(&[page("[]", Some(link))], url).unwrap(),
+            Some("https://example.com/items?page=3".to_string())
+        );
+        assert_eq!(pagination.next_url(&[page("[]", None)], url).unwrap(), None);
+    }
+
+    #[test]
+    fn test_offset_pagination_stops_on_an_empty_page() {
+        let pagination: Pagination = toml::from_str(
+            r#"
+            type = "Offset"
+            param = "offset"
+            items = "/data"
+            "#,
+        )
+        .unwrap();
+        let url = "https://example.com/items?offset=0&limit=2";
+        let pages = vec![
+            page(r#"{"data": [1, 2]}"#, None),
+            page(r#"{"data": [3]}"#, None),
+        ];
+
+        assert_eq!(
+            pagination.next_url(&pages, url).unwrap(),
+            Some("https://example.com/items?limit=2&offset=3".to_string())
+        );
+        assert_eq!(
+            pagination
+                .next_url(&[page(r#"{"data": []}"#, None)], url)
+                .unwrap(),
+            None
+        );
+
+        let merged = pagination.merge(pages).unwrap();
+        assert_eq!(merged.text, "[1,2,3]");
+    }
+
+    #[test]
+    fn test_pages_without_items_are_reported() {
+        let pagination: Pagination = toml::from_str(
+            r#"
+            type = "Page"
+            param = "page"
+            items = "/data"
+            "#,
+        )
+        .unwrap();
+        let url = "https://example.com/items";
+
+        for body in [r#"{"items": [1]}"#, r#"{"data": {"id": 1}}"#] {
+            assert!(matches!(
+                pagination.next_url(&[page(body, None)], url),
+                Err(PaginationError::ItemsNotFound { page: 1, .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_pagination_stops_at_a_failed_page() {
+        let pagination: Pagination = toml::from_str(
+            r#"
+            type = "Page"
+            param = "page"
+            "#,
+        )
+        .unwrap();
+        let url = "https://example.com/items";
+        let pages = vec![
+            page("[1, 2]", None),
+            page_with_status(503, "Service Unavailable", None),
+        ];
+
+        assert_eq!(pagination.next_url(&pages, url).unwrap(), None);
+
+        let merged = pagination.merge(pages).unwrap();
+        assert_eq!(merged.status, 503);
+        assert_eq!(merged.text, "Service Unavailable");
+    }
+}
@@ -0,0 +1,419 @@
+use crate::request::{find_capture, template_placeholders, Dependencies, Dependency, Request};
+use crate::template::TemplateError;
+use std::collections::HashMap;
+use std::fmt;
+use thiserror::Error;
+use tracing::info;
+
+#[derive(Error, Debug)]
+pub enum PlanError {
+    #[error("Request `{request:?}` is defined more than once")]
+    DuplicateRequest { request: String },
+    #[error("Request `{request:?}` was not found in the collection")]
+    RequestNotFound { request: String },
+    #[error("Request `{request:?}` depends on `{dependency:?}`, which is not defined")]
+    UnknownDependency { request: String, dependency: String },
+    #[error("Dependency cycle detected: {}", .cycle.join(" -> "))]
+    CycleDetected { cycle: Vec<String> },
+    #[error("Request `{request:?}` has a template cycle: {}", .cycle.join(" -> "))]
+    TemplateCycle { request: String, cycle: Vec<String> },
+    #[error("Request `{request:?}` has an invalid template: {source}")]
+    InvalidTemplate {
+        request: String,
+        source: TemplateError,
+    },
+}
+
+/// The order in which requests will be executed.
+///
+/// Requests keep the order they are declared in the collection file, except
+/// that a request is always moved after every request it depends on.
+#[derive(Clone, Debug)]
+pub struct Plan {
+    order: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mark {
+    Visiting,
+    Done,
+}
+
+struct Graph<'a> {
+    /// Request names in file order
+    names: Vec<&'a str>,
+    /// Upstream requests of each request, in file order
+    edges: HashMap<&'a str, Vec<&'a str>>,
+}
+
+impl<'a> Graph<'a> {
+    fn build(requests: &'a [Request]) -> Result<Self, PlanError> {
+        let mut positions = HashMap::new();
+        for (index, request) in requests.iter().enumerate() {
+            if positions.insert(request.name.as_str(), index).is_some() {
+                return Err(PlanError::DuplicateRequest {
+                    request: request.name.clone(),
+                });
+            }
+        }
+
+        let mut edges = HashMap::new();
+        for request in requests {
+            let invalid = |source| PlanError::InvalidTemplate {
+                request: request.name.clone(),
+                source,
+            };
+            if let Some(cycle) = template_cycle(request).map_err(invalid)? {
+                return Err(PlanError::TemplateCycle {
+                    request: request.name.clone(),
+                    cycle,
+                });
+            }
+
+            let mut upstream = Vec::new();
+            for dependency in upstream_requests(request, requests).map_err(invalid)? {
+                if !positions.contains_key(dependency) {
+                    return Err(PlanError::UnknownDependency {
+                        request: request.name.clone(),
+                        dependency: dependency.to_string(),
+                    });
+                }
+                if !upstream.contains(&dependency) {
+                    upstream.push(dependency);
+                }
+            }
+            upstream.sort_by_key(|name| positions[name]);
+            edges.insert(request.name.as_str(), upstream);
+        }
+
+        Ok(Self {
+            names: requests
+                .iter()
+                .map(|request| request.name.as_str())
+                .collect(),
+            edges,
+        })
+    }
+
+    /// Depth-first post-order walk, which places every request after its
+    /// dependencies and reports the first cycle it runs into
+    fn visit(
+        &self,
+        name: &'a str,
+        marks: &mut HashMap<&'a str, Mark>,
+        stack: &mut Vec<&'a str>,
+        order: &mut Vec<String>,
+    ) -> Result<(), PlanError> {
+        match marks.get(name) {
+            Some(Mark::Done) => return Ok(()),
+            Some(Mark::Visiting) => {
+                let start = stack.iter().position(|entry| *entry == name).unwrap_or(0);
+                let mut cycle: Vec<String> = stack[start..]
+                    .iter()
+                    .map(|entry| entry.to_string())
+                    .collect();
+                cycle.push(name.to_string());
+                return Err(PlanError::CycleDetected { cycle });
+            }
+            None => {}
+        }
+
+        marks.insert(name, Mark::Visiting);
+        stack.push(name);
+        for dependency in &self.edges[name] {
+            self.visit(dependency, marks, stack, order)?;
+        }
+        stack.pop();
+        marks.insert(name, Mark::Done);
+        order.push(name.to_string());
+
+        Ok(())
+    }
+}
+
+impl Plan {
+    /// Plan the execution of every request in the collection
+    #[tracing::instrument(skip(requests))]
+    pub fn for_collection(requests: &[Request]) -> Result<Self, PlanError> {
+        let graph = Graph::build(requests)?;
+        let mut marks = HashMap::new();
+        let mut order = Vec::with_capacity(requests.len());
+
+        for name in &graph.names {
+            graph.visit(name, &mut marks, &mut Vec::new(), &mut order)?;
+        }
+
+        info!("Planned execution order: {:?}", order);
+        Ok(Self { order })
+    }
+
+    /// Plan the execution of a single request and everything it depends on
+    #[tracing::instrument(skip(requests))]
+    pub fn for_request(requests: &[Request], request: &str) -> Result<Self, PlanError> {
+        let graph = Graph::build(requests)?;
+        let name = graph.names.iter().find(|name| **name == request).ok_or(
+            PlanError::RequestNotFound {
+                request: request.to_string(),
+            },
+        )?;
+        let mut order = Vec::new();
+
+        graph.visit(name, &mut HashMap::new(), &mut Vec::new(), &mut order)?;
+
+        info!("Planned execution order: {:?}", order);
+        Ok(Self { order })
+    }
+
+    pub fn order(&self) -> &[String] {
+        &self.order
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, name) in self.order.iter().enumerate() {
+            writeln!(f, "{}. {}", index + 1, name)?;
+        }
+        Ok(())
+    }
+}
+
+/// The first chain of template dependencies that leads back to itself, such
+/// as `a = "{a}"`, which could never be resolved
+fn template_cycle(request: &Request) -> Result<Option<Vec<String>>, TemplateError> {
+    fn visit<'a>(
+        name: &'a str,
+        dependencies: &'a Dependencies,
+        marks: &mut HashMap<&'a str, Mark>,
+        stack: &mut Vec<&'a str>,
+    ) -> Result<Option<Vec<String>>, TemplateError> {
+        match marks.get(name) {
+            Some(Mark::Done) => return Ok(None),
+            Some(Mark::Visiting) => {
+                let start = stack.iter().position(|entry| *entry == name).unwrap_or(0);
+                let mut cycle: Vec<String> = stack[start..]
+                    .iter()
+                    .map(|entry| entry.to_string())
+                    .collect();
+                cycle.push(name.to_string());
+                return Ok(Some(cycle));
+            }
+            None => {}
+        }
+
+        marks.insert(name, Mark::Visiting);
+        stack.push(name);
+        if let Some(Dependency::Template { template }) = dependencies.get(name) {
+            for placeholder in template_placeholders(template)? {
+                if let Some((placeholder, _)) = dependencies.get_key_value(&placeholder) {
+                    if let Some(cycle) = visit(placeholder, dependencies, marks, stack)? {
+                        return Ok(Some(cycle));
+                    }
+                }
+            }
+        }
+        stack.pop();
+        marks.insert(name, Mark::Done);
+        Ok(None)
+    }
+
+    let Some(dependencies) = &request.dependencies else {
+        return Ok(None);
+    };
+    let mut names: Vec<&str> = dependencies.keys().map(String::as_str).collect();
+    names.sort();
+    let mut marks = HashMap::new();
+    for name in names {
+        if let Some(cycle) = visit(name, dependencies, &mut marks, &mut Vec::new())? {
+            return Ok(Some(cycle));
+        }
+    }
+    Ok(None)
+}
+
+/// Names of the requests whose responses `request` depends on. Only
+/// dependencies that are actually used by a placeholder count, since
+/// inherited defaults may declare more than a request needs.
+fn upstream_requests<'a>(
+    request: &'a Request,
+    requests: &'a [Request],
+) -> Result<impl Iterator<Item = &'a str>, TemplateError> {
+    let placeholders = request.placeholders()?;
+
+    // Placeholders that aren't dependencies or `foreach` variables may refer
+    // to another request's captures
+    let captures: Vec<&str> = placeholders
+        .iter()
+        .filter(|placeholder| {
+            request
+                .dependencies
+                .as_ref()
+                .is_none_or(|dependencies| !dependencies.contains_key(placeholder.as_str()))
+        })
+        .filter(|placeholder| {
+            let variable = placeholder.split('.').next().unwrap_or_default();
+            request
+                .foreach
+                .as_ref()
+                .is_none_or(|foreach| foreach.name != variable)
+        })
+        .filter_map(|placeholder| find_capture(requests, placeholder))
+        .map(|(upstream, _)| upstream.name.as_str())
+        .collect();
+
+    Ok(request
+        .dependencies
+        .iter()
+        .flat_map(|dependencies| dependencies.iter())
+        .filter(move |(name, _)| placeholders.contains(name.as_str()))
+        .filter_map(|(_, dependency)| match dependency {
+            Dependency::Response { request, .. } => Some(request.as_str()),
+            _ => None,
+        })
+        .chain(captures)
+        .chain(
+            request
+                .foreach
+                .iter()
+                .map(|foreach| foreach.request.as_str()),
+        )
+        .chain(
+            request
+                .conditions()
+                .flat_map(|condition| condition.requests()),
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requests(toml: &str) -> Vec<Request> {
+        toml::from_str::<crate::request::Config>(toml)
+            .unwrap()
+            .requests
+    }
+
+    const CHAIN: &str = r#"
+        [[requests]]
+        name = "Get Repository"
+        method = "GET"
+        url = "https://example.com/{repo}"
+        [requests.dependencies.repo]
+        source = "Response"
+        request = "Get Repositories"
+        target = { source = "JsonBody", pointer = "/0/name" }
+
+        [[requests]]
+        name = "Health"
+        method = "GET"
+        url = "https://example.com/health"
+
+        [[requests]]
+        name = "Get Repositories"
+        method = "GET"
+        url = "https://example.com/repos"
+    "#;
+
+    #[test]
+    fn test_plan_keeps_file_order_after_dependencies() {
+        let plan = Plan::for_collection(&requests(CHAIN)).unwrap();
+
+        assert_eq!(
+            plan.order(),
+            ["Get Repositories", "Get Repository", "Health"]
+        );
+    }
+
+    #[test]
+    fn test_plan_for_request_only_includes_its_dependencies() {
+        let plan = Plan::for_request(&requests(CHAIN), "Get Repository").unwrap();
+
+        assert_eq!(plan.order(), ["Get Repositories", "Get Repository"]);
+    }
+
+    #[test]
+    fn test_plan_detects_cycles() {
+        let toml = r#"
+            [[requests]]
+            name = "A"
+            method = "GET"
+            url = "https://example.com/{b}"
+            [requests.dependencies.b]
+            source = "Response"
+            request = "B"
+            target = { source = "JsonBody", pointer = "/b" }
+
+            [[requests]]
+            name = "B"
+            method = "GET"
+            url = "https://example.com/{a}"
+            [requests.dependencies.a]
+            source = "Response"
+            request = "A"
+            target = { source = "JsonBody", pointer = "/a" }
+        "#;
+
+        match Plan::for_collection(&requests(toml)) {
+            Err(PlanError::CycleDetected { cycle }) => assert_eq!(cycle, ["A", "B", "A"]),
+            other => panic!("expected a cycle, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_plan_detects_template_cycles() {
+        let toml = r#"
+            [[requests]]
+            name = "Search"
+            method = "GET"
+            url = "https://example.com/?q={query}"
+            [requests.dependencies]
+            query = { source = "Template", template = "{prefix}:{term}" }
+            prefix = { source = "Template", template = "x-{query}" }
+            term = { source = "Template", template = "term" }
+        "#;
+
+        match Plan::for_collection(&requests(toml)) {
+            Err(PlanError::TemplateCycle { request, cycle }) => {
+                assert_eq!(request, "Search");
+                assert_eq!(cycle, ["prefix", "query", "prefix"]);
+            }
+            other => panic!("expected a template cycle, got {:?}", other),
+        }
+
+        let toml = r#"
+            [[requests]]
+            name = "Echo"
+            method = "GET"
+            url = "https://example.com/{a}"
+            [requests.dependencies]
+            a = { source = "Template", template = "{a}" }
+        "#;
+        assert!(matches!(
+            Plan::for_request(&requests(toml), "Echo"),
+            Err(PlanError::TemplateCycle { .. })
+        ));
+    }
+
+    #[test]
+    fn test_plan_orders_requests_after_the_captures_they_use() {
+        let toml = r#"
+            [[requests]]
+            name = "Get User"
+            method = "GET"
+            url = "https://example.com/user"
+            headers = { Authorization = "Bearer {get_token.access_token}" }
+
+            [[requests]]
+            name = "Get Token"
+            method = "POST"
+            url = "https://example.com/token"
+            [requests.captures]
+            access_token = { source = "JsonBody", pointer = "/access_token" }
+        "#;
+
+        let plan = Plan::for_request(&requests(toml), "Get User").unwrap();
+
+        assert_eq!(plan.order(), ["Get Token", "Get User"]);
+    }
+}
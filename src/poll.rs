@@ -0,0 +1,60 @@
+use crate::condition::Condition;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Re-send a request until its response satisfies a condition, such as
+/// `json("/status") == "completed"`
+#[derive(Clone, Debug, Deserialize)]
+pub struct Poll {
+    /// Checked against every response, which `status()`, `header(name)` and
+    /// `json(pointer)` read without a request name
+    pub until: Condition,
+    /// How long to wait between attempts
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    /// How long to keep polling before giving up
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// How many times to send the request at most, the first time included
+    pub max_attempts: Option<u32>,
+}
+
+fn default_interval_ms() -> u64 {
+    1000
+}
+
+fn default_timeout_ms() -> u64 {
+    60_000
+}
+
+impl Poll {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    /// Whether `attempts` attempts are all that's allowed
+    pub fn out_of_attempts(&self, attempts: u32) -> bool {
+        self.max_attempts.is_some_and(|max| attempts >= max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll_defaults_and_condition() {
+        let poll: Poll = toml::from_str(r#"until = 'json("/status") == "completed"'"#).unwrap();
+
+        assert_eq!(poll.interval(), Duration::from_secs(1));
+        assert_eq!(poll.timeout(), Duration::from_secs(60));
+        assert!(poll.until.requests().is_empty());
+        assert!(!poll.out_of_attempts(1000));
+
+        assert!(toml::from_str::<Poll>(r#"until = 'json("/status") =='"#).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use rand::distributions::{Alphanumeric, DistString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Replace a file that holds secrets. The content goes to a new temporary
+/// file next to it first, which is renamed over the original, so readers
+/// never see a half-written file. The file is only readable by its owner,
+/// and missing parent directories are created.
+pub fn write(path: &Path, content: &[u8]) -> io::Result<()> {
+    // Replace the file a symlink points to, not the symlink
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    fs::create_dir_all(&parent)?;
+
+    let (temporary, mut file) = create_temporary(&path)?;
+    let result = restrict_permissions(&file)
+        .and_then(|()| file.write_all(content))
+        .and_then(|()| file.sync_all())
+        .and_then(|()| fs::rename(&temporary, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result?;
+
+    // Make the rename itself durable
+    #[cfg(unix)]
+    File::open(&parent)?.sync_all()?;
+    Ok(())
+}
+
+/// Create a temporary file with a name nobody else is using. It is never
+/// opened through an existing file or symlink, so a leftover or planted
+/// file can't be written through or keep its own permissions.
+fn create_temporary(path: &Path) -> io::Result<(PathBuf, File)> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    loop {
+        let suffix = Alphanumeric.sample_string(&mut rand::thread_rng(), 12);
+        let temporary = path.with_file_name(format!(".{}.{}.tmp", name, suffix));
+        match options.open(&temporary) {
+            Ok(file) => return Ok((temporary, file)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Make a file readable only by its owner, whatever the umask did
+fn restrict_permissions(file: &File) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    #[cfg(not(unix))]
+    let _ = file;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_replaces_the_file_privately() {
+        let directory = std::env::temp_dir().join(format!("glint-private-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let path = directory.join("secrets.toml");
+
+        write(&path, b"first").unwrap();
+        write(&path, b"second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+
+        // Other files next to it, even ones named like a temporary file, are
+        // left alone
+        let stale = directory.join("secrets.toml.tmp");
+        fs::write(&stale, "stale").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&stale, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+        write(&path, b"third").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third");
+        assert_eq!(fs::read_to_string(&stale).unwrap(), "stale");
+
+        // No temporary file is left behind
+        let mut entries: Vec<String> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        entries.sort();
+        assert_eq!(entries, ["secrets.toml", "secrets.toml.tmp"]);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
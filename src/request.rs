@@ -1,14 +1,68 @@
-use serde::Deserialize;
-use serde_json::Value;
-use std::collections::HashMap;
+use serde::{de, Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
-use crate::masking::MaskingRule;
+use crate::assertions::Assertion;
+use crate::condition::Condition;
+use crate::env_file::EnvFileFormat;
+use crate::masking::{MaskingRule, RegexWrapper};
+use crate::pagination::Pagination;
+use crate::poll::Poll;
+use crate::resolvers::Arguments;
+use crate::retry::RetryPolicy;
+use crate::template::{Template, TemplateError};
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
+    #[serde(default)]
+    pub settings: Settings,
+    #[serde(default)]
+    pub defaults: Defaults,
+    #[serde(default)]
+    pub environments: HashMap<String, Environment>,
     pub requests: Vec<Request>,
 }
 
+/// A named set of variables, selected with `--env`. Placeholders that no
+/// dependency defines are looked up in the variables first, then in the
+/// environment's env file.
+///
+/// Variables have their own table, so that they can have any name. Unknown
+/// fields are rejected, since they would be variables written in the wrong
+/// place.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Environment {
+    pub env_file: Option<String>,
+    /// Detected from the file name if not given
+    pub env_file_format: Option<EnvFileFormat>,
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+}
+
+/// Values every request inherits unless it defines its own
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Defaults {
+    /// Prepended to request URLs that start with `/`
+    pub base_url: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub dependencies: Dependencies,
+    #[serde(default)]
+    pub masking_rules: Vec<MaskingRule>,
+}
+
+/// Collection-wide settings, which individual requests can override
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Settings {
+    /// Maximum time to wait for a whole request, including the response body
+    pub timeout_ms: Option<u64>,
+    /// Maximum time to wait for a connection to be established
+    pub connect_timeout_ms: Option<u64>,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct Request {
     pub name: String,
@@ -19,7 +73,193 @@ pub struct Request {
     pub dependencies: Option<Dependencies>,
     #[serde(default)]
     pub masking_rules: Vec<MaskingRule>,
+    #[serde(default)]
+    pub assertions: Vec<Assertion>,
+    pub retry: Option<RetryPolicy>,
+    pub timeout_ms: Option<u64>,
+    pub connect_timeout_ms: Option<u64>,
+    /// Set to `false` to receive redirect responses instead of following them
+    #[serde(default = "default_follow_redirects")]
+    pub follow_redirects: bool,
+    pub foreach: Option<Foreach>,
+    pub pagination: Option<Pagination>,
+    /// Only send the request if this condition holds, otherwise skip it
+    pub when: Option<Condition>,
+    /// Re-send the request until a condition holds
+    pub poll: Option<Poll>,
+    /// Named values extracted from the response, which other requests use
+    /// as `{request_name.capture}`
+    #[serde(default)]
+    pub captures: HashMap<String, ResponseTarget>,
+    /// Set to `false` to ignore the collection `[defaults]`
+    #[serde(default = "default_inherit_defaults")]
+    pub inherit_defaults: bool,
+}
+
+fn default_inherit_defaults() -> bool {
+    true
+}
+
+fn default_follow_redirects() -> bool {
+    true
 }
+
+impl Request {
+    /// The request's name as used to refer to its captures: lowercase, with
+    /// anything but letters and digits replaced by `_`, so "Get Token"
+    /// becomes `get_token`
+    pub fn slug(&self) -> String {
+        self.name
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect()
+    }
+
+    /// The `when` and `poll` conditions, if any
+    pub fn conditions(&self) -> impl Iterator<Item = &Condition> {
+        self.when
+            .iter()
+            .chain(self.poll.iter().map(|poll| &poll.until))
+    }
+
+    /// Names of the placeholders used in the URL, headers, body and
+    /// conditions, along with those of the template dependencies they use
+    pub fn placeholders(&self) -> Result<HashSet<String>, TemplateError> {
+        let mut templates = vec![self.url.clone()];
+
+        for (key, value) in self.headers.iter().flatten() {
+            templates.push(key.clone());
+            templates.push(value.clone());
+        }
+
+        match &self.body {
+            Some(RequestBody::Text(text)) => templates.push(text.clone()),
+            Some(RequestBody::Json(json)) => json_templates(json, &mut templates),
+            Some(RequestBody::Form(form)) => templates.extend(form.values().cloned()),
+            None => {}
+        }
+
+        let mut placeholders: HashSet<String> = self
+            .conditions()
+            .flat_map(|condition| condition.placeholders())
+            .map(str::to_string)
+            .collect();
+        for template in &templates {
+            placeholders.extend(template_placeholders(template)?);
+        }
+
+        // Template dependencies need their own placeholders, which may be
+        // templates in turn
+        let mut pending: Vec<String> = placeholders.iter().cloned().collect();
+        while let Some(name) = pending.pop() {
+            let Some(Dependency::Template { template }) =
+                self.dependencies.as_ref().and_then(|deps| deps.get(&name))
+            else {
+                continue;
+            };
+            for placeholder in template_placeholders(template)? {
+                if placeholders.insert(placeholder.clone()) {
+                    pending.push(placeholder);
+                }
+            }
+        }
+
+        Ok(placeholders)
+    }
+}
+
+/// Collect the strings of a JSON body, keys included, which are rendered as
+/// templates one by one
+fn json_templates(json: &Value, templates: &mut Vec<String>) {
+    match json {
+        Value::String(text) => templates.push(text.clone()),
+        Value::Array(items) => {
+            for item in items {
+                json_templates(item, templates);
+            }
+        }
+        Value::Object(map) => {
+            for (key, value) in map {
+                templates.push(key.clone());
+                json_templates(value, templates);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Names of the placeholders in a template
+pub fn template_placeholders(template: &str) -> Result<Vec<String>, TemplateError> {
+    Ok(Template::parse(template)?
+        .placeholders()
+        .map(|placeholder| placeholder.name.clone())
+        .collect())
+}
+
+impl Defaults {
+    /// Fill in everything `request` doesn't define itself
+    pub fn apply(&self, request: &mut Request) {
+        if !request.inherit_defaults {
+            return;
+        }
+
+        if let Some(base_url) = &self.base_url {
+            if request.url.is_empty() || request.url.starts_with('/') {
+                request.url = format!("{}{}", base_url.trim_end_matches('/'), request.url);
+            }
+        }
+
+        if !self.headers.is_empty() {
+            let headers = request.headers.get_or_insert_with(HashMap::new);
+            for (key, value) in &self.headers {
+                // Header names are case-insensitive
+                if !headers.keys().any(|name| name.eq_ignore_ascii_case(key)) {
+                    headers.insert(key.clone(), value.clone());
+                }
+            }
+        }
+
+        if !self.dependencies.is_empty() {
+            let dependencies = request.dependencies.get_or_insert_with(HashMap::new);
+            for (name, dependency) in &self.dependencies {
+                dependencies
+                    .entry(name.clone())
+                    .or_insert_with(|| dependency.clone());
+            }
+        }
+
+        // A request's own rule for a path replaces the default rule for it
+        let mut masking_rules: Vec<MaskingRule> = self
+            .masking_rules
+            .iter()
+            .filter(|rule| {
+                !request
+                    .masking_rules
+                    .iter()
+                    .any(|own| own.path == rule.path)
+            })
+            .cloned()
+            .collect();
+        masking_rules.append(&mut request.masking_rules);
+        request.masking_rules = masking_rules;
+    }
+}
+
+/// Run a request once per element of an array taken from another response
+#[derive(Clone, Debug, Deserialize)]
+pub struct Foreach {
+    pub request: String,
+    pub target: ResponseTarget,
+    /// The placeholder each element is available as
+    #[serde(rename = "as", default = "default_foreach_name")]
+    pub name: String,
+}
+
+fn default_foreach_name() -> String {
+    "item".to_string()
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(tag = "type")]
 pub enum RequestBody {
@@ -30,45 +270,318 @@ pub enum RequestBody {
 
 pub type Dependencies = HashMap<String, Dependency>;
 
-#[derive(Clone, Debug, Deserialize)]
-#[serde(tag = "source")]
+#[derive(Clone, Debug)]
 pub enum Dependency {
-    EnvVar {
-        name: String,
-        prompt: Option<String>,
-    },
-    EnvFile {
-        env_file: String,
-        key: String,
-        prompt: Option<String>,
-    },
-    OnePassword {
-        vault: String,
-        item: String,
-        field: String,
-    },
-    File {
-        path: String,
+    Response {
+        request: String,
+        target: ResponseTarget,
     },
-    Prompt {
-        label: String,
+    /// Combine other placeholders, e.g. `{client_id}:{client_secret}`
+    Template { template: String },
+    /// Any other source, such as `EnvVar` or `OnePassword`, resolved by the
+    /// resolver registered under its name
+    Resolver {
+        source: String,
+        arguments: Arguments,
     },
+}
+
+/// The sources that are part of request execution rather than resolvers
+#[derive(Deserialize)]
+#[serde(tag = "source")]
+enum BuiltinDependency {
     Response {
         request: String,
         target: ResponseTarget,
     },
+    Template {
+        template: String,
+    },
+}
+
+impl<'de> Deserialize<'de> for Dependency {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut fields = Map::deserialize(deserializer)?;
+        let source = match fields.get("source") {
+            Some(Value::String(source)) => source.clone(),
+            Some(_) => return Err(de::Error::custom("`source` must be a string")),
+            None => return Err(de::Error::missing_field("source")),
+        };
+
+        match source.as_str() {
+            "Response" | "Template" => Ok(
+                match BuiltinDependency::deserialize(Value::Object(fields))
+                    .map_err(de::Error::custom)?
+                {
+                    BuiltinDependency::Response { request, target } => {
+                        Dependency::Response { request, target }
+                    }
+                    BuiltinDependency::Template { template } => Dependency::Template { template },
+                },
+            ),
+            _ => {
+                fields.remove("source");
+                Ok(Dependency::Resolver {
+                    source,
+                    arguments: Arguments(fields),
+                })
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
 #[serde(tag = "source")]
 pub enum ResponseTarget {
-    HeaderValue { key: String },
-    JsonBody { pointer: String },
+    HeaderValue {
+        key: String,
+    },
+    JsonBody {
+        pointer: String,
+    },
+    /// A single match is extracted as is, several as an array
+    JsonPath {
+        path: String,
+    },
+    /// The first capture group of the pattern, or the whole match if it has
+    /// none, in the body or in a header
+    Regex {
+        pattern: RegexWrapper,
+        header: Option<String>,
+    },
+    Status,
+    /// A cookie set with `Set-Cookie`
+    Cookie {
+        name: String,
+    },
+    /// The URL the response came from, after any redirects
+    FinalUrl,
+    /// A query parameter of the `Location` header, like the `code` of an
+    /// OAuth redirect. Needs `follow_redirects = false`.
+    LocationQuery {
+        param: String,
+    },
+}
+
+impl fmt::Display for ResponseTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResponseTarget::HeaderValue { key } => write!(f, "header {}", key),
+            ResponseTarget::JsonBody { pointer } => write!(f, "body {}", pointer),
+            ResponseTarget::JsonPath { path } => write!(f, "body {}", path),
+            ResponseTarget::Regex {
+                pattern,
+                header: Some(name),
+            } => write!(f, "header {} /{}/", name, pattern.0),
+            ResponseTarget::Regex {
+                pattern,
+                header: None,
+            } => write!(f, "body /{}/", pattern.0),
+            ResponseTarget::Status => write!(f, "status"),
+            ResponseTarget::Cookie { name } => write!(f, "cookie {}", name),
+            ResponseTarget::FinalUrl => write!(f, "final url"),
+            ResponseTarget::LocationQuery { param } => write!(f, "location ?{}", param),
+        }
+    }
+}
+
+/// Find the capture a placeholder like `{get_token.access_token}` refers to,
+/// along with the request that defines it
+pub fn find_capture<'a>(
+    requests: &'a [Request],
+    placeholder: &str,
+) -> Option<(&'a Request, &'a ResponseTarget)> {
+    let (slug, capture) = placeholder.split_once('.')?;
+
+    requests
+        .iter()
+        .filter(|request| request.slug() == slug)
+        .find_map(|request| {
+            request
+                .captures
+                .get(capture)
+                .map(|target| (request, target))
+        })
 }
 
 #[tracing::instrument]
-pub fn load_requests_from_toml(file: &str) -> Result<Vec<Request>, Box<dyn std::error::Error>> {
+pub fn load_config_from_toml(file: &str) -> Result<Config, Box<dyn std::error::Error>> {
     let content = std::fs::read_to_string(file)?;
-    let config: Config = toml::from_str(&content)?;
-    Ok(config.requests)
+    let mut config: Config = toml::from_str(&content)?;
+
+    for request in &mut config.requests {
+        config.defaults.apply(request);
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_are_inherited_unless_overridden() {
+        let config: Config = toml::from_str(
+            r#"
+            [defaults]
+            base_url = "https://api.example.com/"
+            headers = { "Accept" = "application/json", "Authorization" = "Bearer {token}" }
+
+            [[requests]]
+            name = "List"
+            method = "GET"
+            url = "/items"
+            headers = { "accept" = "text/csv" }
+
+            [[requests]]
+            name = "Login"
+            method = "POST"
+            url = "https://auth.example.com/token"
+            inherit_defaults = false
+            "#,
+        )
+        .unwrap();
+        let mut requests = config.requests.clone();
+        for request in &mut requests {
+            config.defaults.apply(request);
+        }
+
+        let list = &requests[0];
+        assert_eq!(list.url, "https://api.example.com/items");
+        assert_eq!(
+            list.headers,
+            Some(HashMap::from([
+                ("accept".to_string(), "text/csv".to_string()),
+                ("Authorization".to_string(), "Bearer {token}".to_string()),
+            ]))
+        );
+        assert_eq!(
+            list.placeholders().unwrap(),
+            HashSet::from(["token".to_string()])
+        );
+
+        let login = &requests[1];
+        assert_eq!(login.url, "https://auth.example.com/token");
+        assert_eq!(login.headers, None);
+    }
+
+    #[test]
+    fn test_placeholders_with_filters() {
+        let request: Request = toml::from_str(
+            r#"
+            name = "Token"
+            method = "POST"
+            url = "https://example.com/token?q={query | urlencode}"
+            headers = { "Authorization" = "Basic {credentials|base64}" }
+            [dependencies.credentials]
+            source = "Template"
+            template = "{client_id}:{secret}"
+            [dependencies.secret]
+            source = "Template"
+            template = "{client_secret}"
+            [dependencies.unused]
+            source = "Template"
+            template = "{other}"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            request.placeholders().unwrap(),
+            HashSet::from(
+                [
+                    "query",
+                    "credentials",
+                    "client_id",
+                    "secret",
+                    "client_secret"
+                ]
+                .map(str::to_string)
+            )
+        );
+
+        // A broken template is reported, not skipped
+        let request: Request = toml::from_str(
+            r#"
+            name = "Token"
+            method = "GET"
+            url = "https://example.com/{secret}"
+            [dependencies.secret]
+            source = "Template"
+            template = "{client secret}"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(request.placeholders().unwrap_err().position, 7);
+    }
+
+    #[test]
+    fn test_placeholders_in_json_bodies() {
+        let mut request: Request =
+            toml::from_str("name = \"Create\"\nmethod = \"POST\"\nurl = \"https://example.com\"")
+                .unwrap();
+        request.body = Some(RequestBody::Json(serde_json::json!({
+            "{field}": {"tags": ["{tag}", 3], "note": "say \"{greeting:-hi}\""},
+            "count": 1,
+        })));
+        assert_eq!(
+            request.placeholders().unwrap(),
+            HashSet::from(["field", "tag", "greeting"].map(str::to_string))
+        );
+
+        // Errors point into the string they're in
+        request.body = Some(RequestBody::Json(serde_json::json!({"name": "{bad name}"})));
+        assert_eq!(request.placeholders().unwrap_err().position, 4);
+    }
+
+    #[test]
+    fn test_captures_are_found_by_slug() {
+        let config: Config = toml::from_str(
+            r#"
+            [[requests]]
+            name = "Get Token"
+            method = "POST"
+            url = "https://example.com/token"
+            [requests.captures]
+            access_token = { source = "JsonBody", pointer = "/access_token" }
+            session = { source = "Regex", header = "Set-Cookie", pattern = "session=([^;]+)" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.requests[0].slug(), "get_token");
+
+        let (request, target) = find_capture(&config.requests, "get_token.access_token").unwrap();
+        assert_eq!(request.name, "Get Token");
+        assert_eq!(target.to_string(), "body /access_token");
+
+        assert!(find_capture(&config.requests, "get_token.refresh_token").is_none());
+        assert!(find_capture(&config.requests, "access_token").is_none());
+    }
+
+    #[test]
+    fn test_dependencies_are_routed_to_resolvers() {
+        let dependencies: Dependencies = toml::from_str(
+            r#"
+            token = { source = "EnvVar", name = "TOKEN", prompt = "Token?" }
+            user = { source = "Response", request = "Get User", target = { source = "JsonBody", pointer = "/id" } }
+            "#,
+        )
+        .unwrap();
+
+        match &dependencies["token"] {
+            Dependency::Resolver { source, arguments } => {
+                assert_eq!(source, "EnvVar");
+                assert_eq!(arguments.0["name"], "TOKEN");
+                assert_eq!(arguments.0["prompt"], "Token?");
+            }
+            other => panic!("expected a resolver dependency, got {:?}", other),
+        }
+        assert!(matches!(dependencies["user"], Dependency::Response { .. }));
+        assert!(toml::from_str::<Dependencies>("x = { name = \"TOKEN\" }").is_err());
+    }
 }
@@ -1,11 +1,273 @@
+pub mod env_file_resolver;
 pub mod env_var_resolver;
+pub mod file_resolver;
 pub mod one_password_resolver;
 pub mod prompt_resolver;
 pub mod response_resolver;
+pub mod vault_resolver;
 
-pub trait Resolver {
-    type Arguments;
-    type Error;
+use async_trait::async_trait;
+use env_file_resolver::EnvFileResolver;
+use env_var_resolver::EnvVarResolver;
+use file_resolver::FileResolver;
+use one_password_resolver::OnePasswordResolver;
+use prompt_resolver::PromptResolver;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use tokio::sync::OnceCell;
+use tracing::debug;
+use vault_resolver::VaultResolver;
 
-    fn resolve(&mut self, arguments: Self::Arguments) -> Result<String, Self::Error>;
+#[derive(Clone, Debug, Error)]
+pub enum ResolverError {
+    #[error("Unknown dependency source `{name}` (expected one of: {})", .known.join(", "))]
+    UnknownSource { name: String, known: Vec<String> },
+    #[error("Invalid arguments: {message}")]
+    InvalidArguments { message: String },
+    #[error(transparent)]
+    Failed(Arc<dyn std::error::Error + Send + Sync>),
+}
+
+impl ResolverError {
+    pub fn failed(error: impl std::error::Error + Send + Sync + 'static) -> Self {
+        Self::Failed(Arc::new(error))
+    }
+}
+
+/// The fields a dependency was declared with, other than `source`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Arguments(pub Map<String, Value>);
+
+impl Arguments {
+    /// Deserialize the arguments into a resolver's own type
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<T, ResolverError> {
+        serde_json::from_value(Value::Object(self.0.clone())).map_err(|error| {
+            ResolverError::InvalidArguments {
+                message: error.to_string(),
+            }
+        })
+    }
+}
+
+impl fmt::Display for Arguments {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Value::Object(self.0.clone()))
+    }
+}
+
+/// A source of placeholder values, such as environment variables or
+/// 1Password. Dependencies pick a resolver by the name it is registered
+/// under, with their `source` field.
+#[async_trait]
+pub trait Resolver: fmt::Debug + Send + Sync {
+    /// Resolve a dependency from the fields it was declared with. Most
+    /// sources resolve to a string, but typed values like numbers keep their
+    /// type in JSON bodies.
+    async fn resolve(&self, arguments: &Arguments) -> Result<Value, ResolverError>;
+
+    /// Resolve a dependency for a dry run, without prompting, running
+    /// commands or saving anything. `None` if the value can't be had that
+    /// way, in which case the dry run shows a stand-in.
+    async fn preview(&self, _arguments: &Arguments) -> Result<Option<Value>, ResolverError> {
+        Ok(None)
+    }
+
+    /// Check a dependency's fields when the collection is loaded, so that
+    /// mistakes are reported before any request is sent
+    fn check(&self, _arguments: &Arguments) -> Result<(), ResolverError> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<R: Resolver + ?Sized> Resolver for Arc<R> {
+    async fn resolve(&self, arguments: &Arguments) -> Result<Value, ResolverError> {
+        (**self).resolve(arguments).await
+    }
+
+    async fn preview(&self, arguments: &Arguments) -> Result<Option<Value>, ResolverError> {
+        (**self).preview(arguments).await
+    }
+
+    fn check(&self, arguments: &Arguments) -> Result<(), ResolverError> {
+        (**self).check(arguments)
+    }
+}
+
+/// The outcome of resolving one dependency, shared by everything that needs it
+type Resolution = Arc<OnceCell<Result<Value, ResolverError>>>;
+
+/// The outcome of previewing one dependency for a dry run
+type Preview = Arc<OnceCell<Result<Option<Value>, ResolverError>>>;
+
+/// The resolvers dependencies can use, by source name.
+///
+/// Every dependency is resolved at most once per run, even when several
+/// requests need it at the same time. Failures are remembered too, so a
+/// cancelled prompt isn't asked again.
+#[derive(Debug, Default)]
+pub struct Registry {
+    resolvers: HashMap<String, Arc<dyn Resolver>>,
+    resolved: Mutex<HashMap<String, Resolution>>,
+    previewed: Mutex<HashMap<String, Preview>>,
+}
+
+impl Registry {
+    /// A registry with the built-in sources: `EnvVar`, `EnvFile`, `File`,
+    /// `OnePassword`, `Prompt` and `Vault`, which uses the vault at `vault`
+    /// and the passphrase in `GLINT_VAULT_PASSPHRASE`, if set
+    pub fn with_defaults(vault: PathBuf) -> Self {
+        // Resolvers that prompt share one terminal
+        let prompt_resolver = Arc::new(PromptResolver::new());
+
+        let mut registry = Self::default();
+        registry.register("EnvVar", EnvVarResolver::new(prompt_resolver.clone()));
+        registry.register("EnvFile", EnvFileResolver::new(prompt_resolver.clone()));
+        registry.register("File", FileResolver);
+        registry.register("OnePassword", OnePasswordResolver::new());
+        let passphrase = std::env::var(crate::vault::PASSPHRASE_VAR).ok();
+        registry.register(
+            "Vault",
+            VaultResolver::new(vault, passphrase, prompt_resolver.clone()),
+        );
+        registry.register("Prompt", prompt_resolver);
+        registry
+    }
+
+    pub fn register(&mut self, source: &str, resolver: impl Resolver + 'static) {
+        self.resolvers
+            .insert(source.to_string(), Arc::new(resolver));
+    }
+
+    fn get(&self, source: &str) -> Result<&Arc<dyn Resolver>, ResolverError> {
+        self.resolvers
+            .get(source)
+            .ok_or_else(|| ResolverError::UnknownSource {
+                name: source.to_string(),
+                known: self.sources(),
+            })
+    }
+
+    /// Names of the registered sources, sorted
+    pub fn sources(&self) -> Vec<String> {
+        let mut sources: Vec<String> = self.resolvers.keys().cloned().collect();
+        sources.sort();
+        sources
+    }
+
+    /// Check that a dependency's source exists and its fields are valid
+    pub fn check(&self, source: &str, arguments: &Arguments) -> Result<(), ResolverError> {
+        self.get(source)?.check(arguments)
+    }
+
+    pub async fn resolve(
+        &self,
+        source: &str,
+        arguments: &Arguments,
+    ) -> Result<Value, ResolverError> {
+        let resolver = self.get(source)?;
+        let cell = self
+            .resolved
+            .lock()
+            .unwrap()
+            .entry(format!("{}{}", source, arguments))
+            .or_default()
+            .clone();
+
+        cell.get_or_init(|| {
+            debug!(source, %arguments, "Resolving dependency");
+            resolver.resolve(arguments)
+        })
+        .await
+        .clone()
+    }
+
+    /// Preview a dependency for a dry run, at most once like `resolve`
+    pub async fn preview(
+        &self,
+        source: &str,
+        arguments: &Arguments,
+    ) -> Result<Option<Value>, ResolverError> {
+        let resolver = self.get(source)?;
+        let cell = self
+            .previewed
+            .lock()
+            .unwrap()
+            .entry(format!("{}{}", source, arguments))
+            .or_default()
+            .clone();
+
+        cell.get_or_init(|| {
+            debug!(source, %arguments, "Previewing dependency");
+            resolver.preview(arguments)
+        })
+        .await
+        .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, Default)]
+    struct Counter(AtomicUsize);
+
+    #[async_trait]
+    impl Resolver for Counter {
+        async fn resolve(&self, arguments: &Arguments) -> Result<Value, ResolverError> {
+            let calls = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            if arguments.0.contains_key("fail") {
+                return Err(ResolverError::InvalidArguments {
+                    message: format!("call #{}", calls),
+                });
+            }
+            Ok(Value::String(format!(
+                "{} #{}",
+                arguments.0["prefix"], calls
+            )))
+        }
+    }
+
+    fn arguments(json: Value) -> Arguments {
+        match json {
+            Value::Object(map) => Arguments(map),
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registry_resolves_each_dependency_once() {
+        let mut registry = Registry::default();
+        registry.register("Counter", Counter::default());
+        let a = arguments(serde_json::json!({"prefix": "a"}));
+        let b = arguments(serde_json::json!({"prefix": "b"}));
+
+        let (first, second) = tokio::join!(
+            registry.resolve("Counter", &a),
+            registry.resolve("Counter", &a)
+        );
+        assert_eq!(first.unwrap(), "\"a\" #1");
+        assert_eq!(second.unwrap(), "\"a\" #1");
+        assert_eq!(registry.resolve("Counter", &b).await.unwrap(), "\"b\" #2");
+
+        // A failure is remembered rather than tried again
+        let failing = arguments(serde_json::json!({"prefix": "c", "fail": true}));
+        for _ in 0..2 {
+            let error = registry.resolve("Counter", &failing).await.unwrap_err();
+            assert_eq!(error.to_string(), "Invalid arguments: call #3");
+        }
+
+        let error = registry.resolve("Vault", &a).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unknown dependency source `Vault` (expected one of: Counter)"
+        );
+    }
 }
@@ -0,0 +1,111 @@
+use super::{prompt_resolver::PromptResolver, Arguments, Resolver, ResolverError};
+use crate::env_file::{self, EnvFileFormat};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::Mutex as AsyncMutex;
+
+#[derive(Error, Debug)]
+pub enum EnvFileResolverError {
+    #[error("Key `{key}` not found in env file `{env_file}`")]
+    KeyNotFound { env_file: String, key: String },
+}
+
+#[derive(Deserialize)]
+struct EnvFileArguments {
+    env_file: String,
+    key: String,
+    prompt: Option<String>,
+    /// Detected from the file name if not given
+    format: Option<EnvFileFormat>,
+    /// Save prompted values to the file's local copy, to keep them out of
+    /// version control
+    #[serde(default)]
+    save_local: bool,
+}
+
+#[derive(Debug)]
+pub struct EnvFileResolver {
+    prompt_resolver: Arc<PromptResolver>,
+    // Held while loading, prompting and saving, so that two prompts for the
+    // same file can't overwrite each other's answers
+    lock: AsyncMutex<()>,
+}
+
+impl EnvFileResolver {
+    /// Create a new `EnvFileResolver`, which asks for missing keys with
+    /// `prompt_resolver` and saves the answers to the file
+    pub fn new(prompt_resolver: Arc<PromptResolver>) -> Self {
+        Self {
+            prompt_resolver,
+            lock: AsyncMutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Resolver for EnvFileResolver {
+    async fn resolve(&self, arguments: &Arguments) -> Result<Value, ResolverError> {
+        let EnvFileArguments {
+            env_file,
+            key,
+            prompt,
+            format,
+            save_local,
+        } = arguments.parse()?;
+        let _lock = self.lock.lock().await;
+
+        // Values saved locally don't need the shared file to exist
+        let env_data = if save_local {
+            env_file::load_local(&env_file, format)
+        } else {
+            env_file::load(&env_file, format)
+        }
+        .map_err(ResolverError::failed)?;
+        if let Some(value) = env_file::lookup(&env_data, &key) {
+            return Ok(value.clone());
+        }
+
+        let Some(prompt) = prompt else {
+            return Err(ResolverError::failed(EnvFileResolverError::KeyNotFound {
+                env_file,
+                key,
+            }));
+        };
+        let value = self
+            .prompt_resolver
+            .prompt(&prompt)
+            .await
+            .map_err(ResolverError::failed)?;
+
+        // Save the answer for next time
+        env_file::insert(&env_file, format, &key, &value, save_local)
+            .map_err(ResolverError::failed)?;
+
+        Ok(Value::String(value))
+    }
+
+    /// The value if the file has it, without asking for it otherwise
+    async fn preview(&self, arguments: &Arguments) -> Result<Option<Value>, ResolverError> {
+        let EnvFileArguments {
+            env_file,
+            key,
+            format,
+            save_local,
+            ..
+        } = arguments.parse()?;
+        let env_data = if save_local {
+            env_file::load_local(&env_file, format)
+        } else {
+            env_file::load(&env_file, format)
+        }
+        .map_err(ResolverError::failed)?;
+        Ok(env_file::lookup(&env_data, &key).cloned())
+    }
+
+    fn check(&self, arguments: &Arguments) -> Result<(), ResolverError> {
+        arguments.parse::<EnvFileArguments>().map(drop)
+    }
+}
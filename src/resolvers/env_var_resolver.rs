@@ -1,8 +1,9 @@
-use super::{
-    prompt_resolver::{PromptResolver, PromptResolverError},
-    Resolver,
-};
+use super::{prompt_resolver::PromptResolver, Arguments, Resolver, ResolverError};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use thiserror::Error;
 use tracing::info;
 
@@ -10,44 +11,45 @@ use tracing::info;
 pub enum EnvVarResolverError {
     #[error("Environment variable `{name}` not found")]
     EnvVarNotFound { name: String },
-    #[error("Prompt error")]
-    PromptError(#[from] PromptResolverError),
+}
+
+#[derive(Deserialize)]
+struct EnvVarArguments {
+    name: String,
+    prompt: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct EnvVarResolver {
-    cache: HashMap<String, String>,
-    prompt_resolver: PromptResolver,
+    cache: Mutex<HashMap<String, String>>,
+    prompt_resolver: Arc<PromptResolver>,
 }
 
 impl EnvVarResolver {
-    /// Create a new `EnvVarResolver`
-    pub fn new() -> Self {
+    /// Create a new `EnvVarResolver`, which asks for variables that aren't
+    /// set with `prompt_resolver`
+    pub fn new(prompt_resolver: Arc<PromptResolver>) -> Self {
         Self {
-            cache: HashMap::new(),
-            prompt_resolver: PromptResolver::new(),
+            cache: Mutex::new(HashMap::new()),
+            prompt_resolver,
         }
     }
 
     /// Save resolved environment variable to cache
-    pub fn save_to_cache(&mut self, key: String, value: String) -> Option<String> {
+    pub fn save_to_cache(&self, key: String, value: String) -> Option<String> {
         info!("Caching environment variable");
-        self.cache.insert(key, value)
+        self.cache.lock().unwrap().insert(key, value)
     }
-}
-
-impl Resolver for EnvVarResolver {
-    type Arguments = (String, Option<String>);
-    type Error = EnvVarResolverError;
 
     /// Resolve an environment variable, checking the cache first
     #[tracing::instrument]
-    fn resolve(
-        &mut self,
-        (name, prompt): (String, Option<String>),
+    async fn resolve_variable(
+        &self,
+        name: String,
+        prompt: Option<String>,
     ) -> Result<String, EnvVarResolverError> {
         // Check the cache first
-        if let Some(cached_value) = self.cache.get(&name) {
+        if let Some(cached_value) = self.cache.lock().unwrap().get(&name) {
             info!("Cache hit for environment variable");
             return Ok(cached_value.clone());
         }
@@ -60,11 +62,8 @@ impl Resolver for EnvVarResolver {
                 Ok(value)
             }
             Err(_) => {
-                if let Ok(value) = self
-                    .prompt_resolver
-                    .resolve(name.clone())
-                    .map_err(|_| EnvVarResolverError::EnvVarNotFound { name: name.clone() })
-                {
+                let key = prompt.unwrap_or_else(|| name.clone());
+                if let Ok(value) = self.prompt_resolver.prompt(&key).await {
                     self.save_to_cache(name, value.clone());
                     return Ok(value);
                 }
@@ -75,3 +74,27 @@ impl Resolver for EnvVarResolver {
         }
     }
 }
+
+#[async_trait]
+impl Resolver for EnvVarResolver {
+    async fn resolve(&self, arguments: &Arguments) -> Result<Value, ResolverError> {
+        let EnvVarArguments { name, prompt } = arguments.parse()?;
+        self.resolve_variable(name, prompt)
+            .await
+            .map(Value::String)
+            .map_err(ResolverError::failed)
+    }
+
+    /// The variable if it is set, without asking for it otherwise
+    async fn preview(&self, arguments: &Arguments) -> Result<Option<Value>, ResolverError> {
+        let EnvVarArguments { name, .. } = arguments.parse()?;
+        let cached = self.cache.lock().unwrap().get(&name).cloned();
+        Ok(cached
+            .or_else(|| std::env::var(&name).ok())
+            .map(Value::String))
+    }
+
+    fn check(&self, arguments: &Arguments) -> Result<(), ResolverError> {
+        arguments.parse::<EnvVarArguments>().map(drop)
+    }
+}
@@ -0,0 +1,42 @@
+use super::{Arguments, Resolver, ResolverError};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum FileResolverError {
+    #[error("Failed to read `{path}`: {source}")]
+    ReadFailed {
+        path: String,
+        source: std::io::Error,
+    },
+}
+
+#[derive(Deserialize)]
+struct FileArguments {
+    path: String,
+}
+
+/// Reads a value from a file, without surrounding whitespace
+#[derive(Debug)]
+pub struct FileResolver;
+
+#[async_trait]
+impl Resolver for FileResolver {
+    async fn resolve(&self, arguments: &Arguments) -> Result<Value, ResolverError> {
+        let FileArguments { path } = arguments.parse()?;
+        let content = tokio::fs::read_to_string(&path).await.map_err(|source| {
+            ResolverError::failed(FileResolverError::ReadFailed { path, source })
+        })?;
+        Ok(Value::String(content.trim().to_string()))
+    }
+
+    async fn preview(&self, arguments: &Arguments) -> Result<Option<Value>, ResolverError> {
+        self.resolve(arguments).await.map(Some)
+    }
+
+    fn check(&self, arguments: &Arguments) -> Result<(), ResolverError> {
+        arguments.parse::<FileArguments>().map(drop)
+    }
+}
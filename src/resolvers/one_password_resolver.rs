@@ -1,8 +1,11 @@
-use std::process::Command;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
 use thiserror::Error;
+use tokio::process::Command;
 use tracing::info;
 
-use super::Resolver;
+use super::{Arguments, Resolver, ResolverError};
 
 /// Errors that may occur when resolving 1Password variables
 #[derive(Error, Debug)]
@@ -26,6 +29,13 @@ pub enum OnePasswordResolverError {
     FieldNotFound { vault: String, item: String, field: String },
 }
 
+#[derive(Deserialize)]
+struct OnePasswordArguments {
+    vault: String,
+    item: String,
+    field: String,
+}
+
 #[derive(Debug)]
 pub struct OnePasswordResolver;
 
@@ -33,17 +43,15 @@ impl OnePasswordResolver {
     pub fn new() -> Self {
         Self
     }
-}
-
-impl Resolver for OnePasswordResolver {
-    type Arguments = (String, String, String); // (vault, item, field)
-    type Error = OnePasswordResolverError;
 
-    /// Resolve a variable from 1Password using the CLI
+    /// Resolve a variable from 1Password using the CLI. Reads run as
+    /// separate processes, so several can be in flight at once.
     #[tracing::instrument]
-    fn resolve(
-        &mut self,
-        (vault, item, field): Self::Arguments,
+    async fn read(
+        &self,
+        vault: String,
+        item: String,
+        field: String,
     ) -> Result<String, OnePasswordResolverError> {
         let reference = format!("op://{}/{}/{}", vault, item, field);
 
@@ -51,7 +59,8 @@ impl Resolver for OnePasswordResolver {
         let output = Command::new("op")
             .arg("read")
             .arg(&reference)
-            .output();
+            .output()
+            .await;
 
         match output {
             Ok(output) if output.status.success() => {
@@ -86,3 +95,17 @@ impl Resolver for OnePasswordResolver {
     }
 }
 
+#[async_trait]
+impl Resolver for OnePasswordResolver {
+    async fn resolve(&self, arguments: &Arguments) -> Result<Value, ResolverError> {
+        let OnePasswordArguments { vault, item, field } = arguments.parse()?;
+        self.read(vault, item, field)
+            .await
+            .map(Value::String)
+            .map_err(ResolverError::failed)
+    }
+
+    fn check(&self, arguments: &Arguments) -> Result<(), ResolverError> {
+        arguments.parse::<OnePasswordArguments>().map(drop)
+    }
+}
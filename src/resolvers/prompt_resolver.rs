@@ -1,7 +1,11 @@
-use super::Resolver;
-use dialoguer::{theme::ColorfulTheme, Input};
+use super::{Arguments, Resolver, ResolverError};
+use async_trait::async_trait;
+use dialoguer::{theme::ColorfulTheme, Input, Password};
+use serde::Deserialize;
+use serde_json::Value;
 use std::collections::HashMap;
 use thiserror::Error;
+use tokio::sync::Mutex;
 use tracing::info;
 
 #[derive(Error, Debug)]
@@ -10,56 +14,92 @@ pub enum PromptResolverError {
     PromptFailed { key: String },
 }
 
+#[derive(Deserialize)]
+struct PromptArguments {
+    label: String,
+}
+
 #[derive(Debug)]
 pub struct PromptResolver {
-    cache: HashMap<String, String>,
+    // Also keeps the terminal to one question at a time
+    cache: Mutex<HashMap<String, String>>,
 }
 
 impl PromptResolver {
     /// Create a new `PromptResolver`
     pub fn new() -> Self {
         Self {
-            cache: HashMap::new(),
+            cache: Mutex::new(HashMap::new()),
         }
     }
 
-    /// Save a user-provided value to the cache
-    fn save_to_cache(&mut self, key: String, value: String) -> Option<String> {
-        info!("Caching user input: {} = {}", key, value);
-        self.cache.insert(key, value)
-    }
-
     /// Prompt the user for input
-    fn prompt_user(&self, key: &str) -> Result<String, PromptResolverError> {
-        Input::with_theme(&ColorfulTheme::default())
-            .with_prompt(format!("Enter value for {}", key))
-            .interact_text()
-            .map_err(|_| PromptResolverError::PromptFailed {
-                key: key.to_string(),
-            })
+    async fn prompt_user(key: &str) -> Result<String, PromptResolverError> {
+        let prompt = format!("Enter value for {}", key);
+        tokio::task::spawn_blocking(move || {
+            Input::<String>::with_theme(&ColorfulTheme::default())
+                .with_prompt(prompt)
+                .interact_text()
+        })
+        .await
+        .ok()
+        .and_then(Result::ok)
+        .ok_or_else(|| PromptResolverError::PromptFailed {
+            key: key.to_string(),
+        })
     }
-}
 
-impl Resolver for PromptResolver {
-    type Arguments = String;
-    type Error = PromptResolverError;
+    /// Ask the user for a secret without echoing it. Secrets aren't cached.
+    pub async fn secret(&self, label: &str) -> Result<String, PromptResolverError> {
+        let _terminal = self.cache.lock().await;
+        let prompt = label.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            Password::with_theme(&ColorfulTheme::default())
+                .with_prompt(prompt)
+                .interact()
+        })
+        .await
+        .ok()
+        .and_then(Result::ok)
+        .ok_or_else(|| PromptResolverError::PromptFailed {
+            key: label.to_string(),
+        })
+    }
 
-    /// Resolve a user input value, caching the result
+    /// Ask the user for a value, caching the answer
     #[tracing::instrument]
-    fn resolve(&mut self, key: String) -> Result<String, PromptResolverError> {
+    pub async fn prompt(&self, key: &str) -> Result<String, PromptResolverError> {
+        let mut cache = self.cache.lock().await;
+
         // Check the cache first
-        if let Some(cached_value) = self.cache.get(&key) {
+        if let Some(cached_value) = cache.get(key) {
             info!("Cache hit for user input: {}", key);
             return Ok(cached_value.clone());
         }
 
         // Prompt the user for input if not in cache
-        let value = self.prompt_user(&key)?;
+        let value = Self::prompt_user(key).await?;
         info!("Resolved user input: {} = {}", key, value);
 
         // Save the value to the cache
-        self.save_to_cache(key.clone(), value.clone());
+        cache.insert(key.to_string(), value.clone());
 
         Ok(value)
     }
 }
+
+#[async_trait]
+impl Resolver for PromptResolver {
+    async fn resolve(&self, arguments: &Arguments) -> Result<Value, ResolverError> {
+        let PromptArguments { label } = arguments.parse()?;
+        self.prompt(&label)
+            .await
+            .map(Value::String)
+            .map_err(ResolverError::failed)
+    }
+
+    fn check(&self, arguments: &Arguments) -> Result<(), ResolverError> {
+        arguments.parse::<PromptArguments>().map(drop)
+    }
+}
@@ -1,5 +1,7 @@
-use super::Resolver;
 use crate::{request::ResponseTarget, response::Response};
+use regex::Regex;
+use reqwest::header::{LOCATION, SET_COOKIE};
+use serde_json::Value;
 use std::collections::HashMap;
 use thiserror::Error;
 use tracing::{debug, error, warn};
@@ -14,11 +16,25 @@ pub enum ResponseResolverError {
     HeaderNotFound { key: String, request: String },
     #[error("Invalid format for header `{key:?}` in request {request:?}")]
     InvalidHeaderFormat { key: String, request: String },
+    #[error("Invalid JSONPath `{path:?}` for request {request:?}: {message}")]
+    InvalidJsonPath {
+        path: String,
+        request: String,
+        message: String,
+    },
+    #[error("Pattern `{pattern}` did not match the response of request {request:?}")]
+    NoMatch { pattern: String, request: String },
+    #[error("Cookie `{name:?}` not found in request {request:?}")]
+    CookieNotFound { name: String, request: String },
+    #[error("Invalid `Location` header in request {request:?}: {location:?}")]
+    InvalidLocation { location: String, request: String },
+    #[error("Query parameter `{param:?}` not found in the `Location` of request {request:?}")]
+    QueryParameterNotFound { param: String, request: String },
 }
 
 #[derive(Debug)]
 pub struct ResponseResolver {
-    history: HashMap<String, Response>,
+    history: HashMap<String, Vec<Response>>,
 }
 
 impl ResponseResolver {
@@ -28,10 +44,31 @@ impl ResponseResolver {
         }
     }
 
+    /// Save a response. Requests that run more than once (with `foreach`)
+    /// keep every response, and targets resolve against the latest one.
     #[tracing::instrument]
-    pub fn save_to_history(&mut self, response: Response) -> Option<Response> {
+    pub fn save_to_history(&mut self, response: Response) {
         debug!("Saving response: {:?}", response);
-        self.history.insert(response.request.name.clone(), response)
+        self.history
+            .entry(response.request.name.clone())
+            .or_default()
+            .push(response);
+    }
+
+    /// The most recent response of a request
+    pub fn latest(&self, request: &str) -> Option<&Response> {
+        self.history
+            .get(request)
+            .and_then(|responses| responses.last())
+    }
+
+    fn response(&self, request: &String) -> Result<&Response, ResponseResolverError> {
+        self.latest(request).ok_or_else(|| {
+            warn!("Request '{}' not found in history", request);
+            ResponseResolverError::RequestNotFound {
+                request: request.clone(),
+            }
+        })
     }
 
     fn resolve_header(
@@ -40,30 +77,30 @@ impl ResponseResolver {
         key: &String,
     ) -> Result<String, ResponseResolverError> {
         debug!("Resolving header '{}' for request '{}'", key, request);
-        if let Some(response) = self.history.get(request) {
+        if let Some(response) = self.latest(request) {
             debug!("Found response for request '{}': {:?}", request, response);
             if let Some(value) = response.headers.get(key) {
                 debug!("Found header '{}' in request '{}'", key, request);
                 if let Ok(value_str) = value.to_str() {
-                    return Ok(value_str.to_string());
+                    Ok(value_str.to_string())
                 } else {
-                    return Err(ResponseResolverError::InvalidHeaderFormat {
+                    Err(ResponseResolverError::InvalidHeaderFormat {
                         key: key.clone(),
                         request: request.clone(),
-                    });
+                    })
                 }
             } else {
                 error!("Header '{}' not found in request '{}'", key, request);
-                return Err(ResponseResolverError::HeaderNotFound {
+                Err(ResponseResolverError::HeaderNotFound {
                     key: key.clone(),
                     request: request.clone(),
-                });
+                })
             }
         } else {
             warn!("Request '{}' not found in history", request);
-            return Err(ResponseResolverError::RequestNotFound {
+            Err(ResponseResolverError::RequestNotFound {
                 request: request.clone(),
-            });
+            })
         }
     }
 
@@ -71,51 +108,247 @@ impl ResponseResolver {
         &self,
         request: &String,
         path: &String,
-    ) -> Result<String, ResponseResolverError> {
+    ) -> Result<Value, ResponseResolverError> {
         debug!("Resolving JSON path '{}' for request '{}'", path, request);
-        if let Some(response) = self.history.get(request) {
+        if let Some(response) = self.latest(request) {
             if let Ok(json) = &response.json() {
                 debug!("Found JSON body for request '{}': {:?}", request, json);
                 if let Some(extracted) = json.pointer(path) {
-                    if extracted.is_null() {
-                        return Ok("".to_string());
-                    } else if let Some(value_str) = extracted.as_str() {
-                        return Ok(value_str.to_string());
-                    } else {
-                        return Ok(extracted.to_string());
-                    }
+                    Ok(extracted.clone())
                 } else {
                     error!("Path '{}' not found in {:?}", path, response);
-                    return Err(ResponseResolverError::InvalidPath {
+                    Err(ResponseResolverError::InvalidPath {
                         path: path.clone(),
                         request: request.clone(),
-                    });
+                    })
                 }
             } else {
-                return Err(ResponseResolverError::InvalidPath {
+                Err(ResponseResolverError::InvalidPath {
                     path: path.clone(),
                     request: request.clone(),
-                });
+                })
             }
         } else {
-            return Err(ResponseResolverError::RequestNotFound {
+            Err(ResponseResolverError::RequestNotFound {
                 request: request.clone(),
-            });
+            })
         }
     }
-}
 
-impl Resolver for ResponseResolver {
-    type Arguments = (String, ResponseTarget);
-    type Error = ResponseResolverError;
+    /// Select values with a JSONPath. A single match is returned as is,
+    /// several as an array.
+    fn resolve_json_path(
+        &self,
+        request: &String,
+        path: &String,
+    ) -> Result<Value, ResponseResolverError> {
+        debug!("Resolving JSONPath '{}' for request '{}'", path, request);
+        let response = self.response(request)?;
+        let invalid_path = || ResponseResolverError::InvalidPath {
+            path: path.clone(),
+            request: request.clone(),
+        };
+        let json = response.json().map_err(|_| invalid_path())?;
+        let matches = jsonpath_lib::select(&json, path).map_err(|error| {
+            ResponseResolverError::InvalidJsonPath {
+                path: path.clone(),
+                request: request.clone(),
+                message: error.to_string(),
+            }
+        })?;
+
+        match matches.as_slice() {
+            [] => Err(invalid_path()),
+            [single] => Ok((*single).clone()),
+            _ => Ok(Value::Array(matches.into_iter().cloned().collect())),
+        }
+    }
+
+    /// Match a pattern against the body, or a header if one is named, and
+    /// extract its first capture group (or the whole match)
+    fn resolve_regex(
+        &self,
+        request: &String,
+        pattern: &Regex,
+        header: Option<&String>,
+    ) -> Result<String, ResponseResolverError> {
+        let subject = match header {
+            Some(key) => self.resolve_header(request, key)?,
+            None => self.response(request)?.text.clone(),
+        };
+        let captures =
+            pattern
+                .captures(&subject)
+                .ok_or_else(|| ResponseResolverError::NoMatch {
+                    pattern: pattern.to_string(),
+                    request: request.clone(),
+                })?;
+
+        Ok(captures
+            .get(1)
+            .or_else(|| captures.get(0))
+            .map_or("", |matched| matched.as_str())
+            .to_string())
+    }
+
+    /// The value of a cookie set by the response
+    fn resolve_cookie(
+        &self,
+        request: &String,
+        name: &String,
+    ) -> Result<String, ResponseResolverError> {
+        self.response(request)?
+            .headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|header| header.to_str().ok())
+            .filter_map(|cookie| cookie.split(';').next()?.split_once('='))
+            .find(|(key, _)| key.trim() == name)
+            .map(|(_, value)| value.trim().to_string())
+            .ok_or_else(|| ResponseResolverError::CookieNotFound {
+                name: name.clone(),
+                request: request.clone(),
+            })
+    }
 
-    fn resolve(
-        &mut self,
-        (request, resolution_type): (String, ResponseTarget),
+    /// A query parameter of the URL the response redirects to, such as the
+    /// `code` of an OAuth callback. Relative locations are resolved against
+    /// the response's URL.
+    fn resolve_location_query(
+        &self,
+        request: &String,
+        param: &String,
     ) -> Result<String, ResponseResolverError> {
-        match resolution_type {
-            ResponseTarget::HeaderValue { key } => self.resolve_header(&request, &key),
-            ResponseTarget::JsonBody { pointer } => self.resolve_body(&request, &pointer),
+        let location = self.resolve_header(request, &LOCATION.to_string())?;
+        let url = self.response(request)?.url.join(&location).map_err(|_| {
+            ResponseResolverError::InvalidLocation {
+                location: location.clone(),
+                request: request.clone(),
+            }
+        })?;
+
+        url.query_pairs()
+            .find(|(key, _)| key == param)
+            .map(|(_, value)| value.into_owned())
+            .ok_or_else(|| ResponseResolverError::QueryParameterNotFound {
+                param: param.clone(),
+                request: request.clone(),
+            })
+    }
+
+    /// Resolve a target as JSON, so that values taken from a JSON body keep
+    /// their type. Header values are always strings.
+    pub fn resolve_value(
+        &self,
+        request: &String,
+        target: &ResponseTarget,
+    ) -> Result<Value, ResponseResolverError> {
+        match target {
+            ResponseTarget::HeaderValue { key } => {
+                self.resolve_header(request, key).map(Value::String)
+            }
+            ResponseTarget::JsonBody { pointer } => self.resolve_body(request, pointer),
+            ResponseTarget::JsonPath { path } => self.resolve_json_path(request, path),
+            ResponseTarget::Regex { pattern, header } => self
+                .resolve_regex(request, &pattern.0, header.as_ref())
+                .map(Value::String),
+            ResponseTarget::Status => Ok(Value::from(self.response(request)?.status.as_u16())),
+            ResponseTarget::Cookie { name } => {
+                self.resolve_cookie(request, name).map(Value::String)
+            }
+            ResponseTarget::FinalUrl => Ok(Value::String(self.response(request)?.url.to_string())),
+            ResponseTarget::LocationQuery { param } => self
+                .resolve_location_query(request, param)
+                .map(Value::String),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderMap;
+
+    #[test]
+    fn test_resolve_redirect_targets() {
+        let mut headers = HeaderMap::new();
+        headers.append(SET_COOKIE, "theme=dark; Path=/".parse().unwrap());
+        headers.append(SET_COOKIE, "session=abc123; HttpOnly".parse().unwrap());
+        headers.insert(LOCATION, "/callback?code=xyz%3D&state=1".parse().unwrap());
+
+        let mut resolver = ResponseResolver::new();
+        resolver.save_to_history(Response::fixture(
+            r#"
+            name = "Authorize"
+            method = "GET"
+            url = "https://example.com/authorize"
+            "#,
+            302,
+            headers,
+            r#"<input name="csrf" value="t0k3n">"#,
+        ));
+        resolver.save_to_history(Response::fixture(
+            r#"
+            name = "Token"
+            method = "POST"
+            url = "https://example.com/token"
+            "#,
+            200,
+            HeaderMap::new(),
+            r#"{"scopes": [{"name": "read"}, {"name": "write"}]}"#,
+        ));
+        assert_eq!(
+            resolver
+                .resolve_value(
+                    &"Token".to_string(),
+                    &toml::from_str(
+                        r#"source = "JsonPath"
+                           path = "$.scopes[*].name""#
+                    )
+                    .unwrap()
+                )
+                .unwrap(),
+            serde_json::json!(["read", "write"])
+        );
+        let resolve = |target: &str| {
+            resolver.resolve_value(&"Authorize".to_string(), &toml::from_str(target).unwrap())
+        };
+
+        assert_eq!(resolve(r#"source = "Status""#).unwrap(), 302);
+        assert_eq!(
+            resolve(
+                r#"source = "Cookie"
+                       name = "session""#
+            )
+            .unwrap(),
+            "abc123"
+        );
+        assert_eq!(
+            resolve(r#"source = "FinalUrl""#).unwrap(),
+            "https://example.com/authorize"
+        );
+        assert_eq!(
+            resolve(
+                r#"source = "LocationQuery"
+                       param = "code""#
+            )
+            .unwrap(),
+            "xyz="
+        );
+        assert_eq!(
+            resolve(
+                r#"source = "Regex"
+                       pattern = 'name="csrf" value="([^"]+)"'"#
+            )
+            .unwrap(),
+            "t0k3n"
+        );
+        assert!(matches!(
+            resolve(
+                r#"source = "Cookie"
+                       name = "missing""#
+            ),
+            Err(ResponseResolverError::CookieNotFound { .. })
+        ));
+    }
+}
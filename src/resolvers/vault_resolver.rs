@@ -0,0 +1,151 @@
+use super::{prompt_resolver::PromptResolver, Arguments, Resolver, ResolverError};
+use crate::vault::{Vault, VaultError};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tracing::info;
+
+#[derive(Deserialize)]
+struct VaultArguments {
+    key: String,
+    prompt: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum VaultResolverError {
+    #[error("The vault couldn't be unlocked: {reason}")]
+    Unavailable { reason: String },
+}
+
+#[derive(Debug)]
+enum State {
+    Locked,
+    Unlocked(Vault),
+    /// Unlocking failed, and isn't tried again so the passphrase is only
+    /// asked for once
+    Failed(String),
+}
+
+/// Reads values from the encrypted vault, which is unlocked the first time
+/// a value is needed
+#[derive(Debug)]
+pub struct VaultResolver {
+    path: PathBuf,
+    /// Asked for when unlocking if not given
+    passphrase: Option<String>,
+    prompt_resolver: Arc<PromptResolver>,
+    state: Mutex<State>,
+}
+
+impl VaultResolver {
+    pub fn new(
+        path: PathBuf,
+        passphrase: Option<String>,
+        prompt_resolver: Arc<PromptResolver>,
+    ) -> Self {
+        Self {
+            path,
+            passphrase,
+            prompt_resolver,
+            state: Mutex::new(State::Locked),
+        }
+    }
+
+    async fn unlock(&self) -> Result<Vault, ResolverError> {
+        let passphrase = match &self.passphrase {
+            Some(passphrase) => passphrase.clone(),
+            None => self
+                .prompt_resolver
+                .secret("Vault passphrase")
+                .await
+                .map_err(ResolverError::failed)?,
+        };
+        Vault::open(&self.path, &passphrase).map_err(ResolverError::failed)
+    }
+}
+
+#[async_trait]
+impl Resolver for VaultResolver {
+    async fn resolve(&self, arguments: &Arguments) -> Result<Value, ResolverError> {
+        let VaultArguments { key, prompt } = arguments.parse()?;
+        let mut state = self.state.lock().await;
+        if let State::Locked = *state {
+            *state = match self.unlock().await {
+                Ok(vault) => State::Unlocked(vault),
+                Err(error) => State::Failed(error.to_string()),
+            };
+        }
+        let vault = match &mut *state {
+            State::Unlocked(vault) => vault,
+            State::Failed(reason) => {
+                return Err(ResolverError::failed(VaultResolverError::Unavailable {
+                    reason: reason.clone(),
+                }))
+            }
+            State::Locked => unreachable!("unlocking was just tried"),
+        };
+
+        if let Some(value) = vault.get(&key) {
+            return Ok(Value::String(value.to_string()));
+        }
+        let Some(prompt) = prompt else {
+            return Err(ResolverError::failed(VaultError::KeyNotFound { key }));
+        };
+
+        // Keep the answer in the vault rather than in a plaintext file
+        let value = self
+            .prompt_resolver
+            .secret(&prompt)
+            .await
+            .map_err(ResolverError::failed)?;
+        vault.set(&key, &value);
+        vault.save().map_err(ResolverError::failed)?;
+        info!("Saved `{}` to the vault", key);
+
+        Ok(Value::String(value))
+    }
+
+    fn check(&self, arguments: &Arguments) -> Result<(), ResolverError> {
+        arguments.parse::<VaultArguments>().map(drop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_failed_unlock_is_not_retried() {
+        let path = std::env::temp_dir().join(format!("glint-locked-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let resolver = VaultResolver::new(
+            path.clone(),
+            Some("passphrase".to_string()),
+            Arc::new(PromptResolver::new()),
+        );
+        let arguments = Arguments(
+            serde_json::json!({"key": "token"})
+                .as_object()
+                .unwrap()
+                .clone(),
+        );
+
+        let error = resolver.resolve(&arguments).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("The vault couldn't be unlocked"));
+
+        // Creating the vault afterwards doesn't help this run
+        Vault::create(&path, "passphrase").unwrap().save().unwrap();
+        let error = resolver.resolve(&arguments).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("The vault couldn't be unlocked"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
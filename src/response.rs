@@ -1,6 +1,7 @@
 use crate::request::Request;
-use reqwest::{header::HeaderMap, StatusCode};
+use reqwest::{header::HeaderMap, StatusCode, Url};
 use serde_json::Value;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -14,7 +15,10 @@ pub struct Response {
     pub request: Request,
     pub headers: HeaderMap,
     pub status: StatusCode,
+    /// The URL the response came from, after any redirects
+    pub url: Url,
     pub text: String,
+    pub elapsed: Duration,
 }
 
 impl Response {
@@ -22,3 +26,20 @@ impl Response {
         serde_json::from_str::<Value>(&self.text).map_err(ResponseError::from)
     }
 }
+
+#[cfg(test)]
+impl Response {
+    /// A response to the request described by `request` (as TOML), from the
+    /// request's URL
+    pub fn fixture(request: &str, status: u16, headers: HeaderMap, text: &str) -> Self {
+        let request: Request = toml::from_str(request).unwrap();
+        Self {
+            url: request.url.parse().unwrap(),
+            request,
+            headers,
+            status: StatusCode::from_u16(status).unwrap(),
+            text: text.to_string(),
+            elapsed: Duration::from_millis(10),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub enum Backoff {
+    /// Wait `delay_ms` between every attempt
+    Fixed,
+    /// Double the wait after every attempt, starting at `delay_ms`
+    #[default]
+    Exponential,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default)]
+    pub backoff: Backoff,
+    #[serde(default = "default_delay_ms")]
+    pub delay_ms: u64,
+    /// Upper bound for every delay. A `Retry-After` longer than this stops
+    /// the retries instead.
+    pub max_delay_ms: Option<u64>,
+    /// Randomly shorten each delay by up to half, to spread out retries
+    #[serde(default)]
+    pub jitter: bool,
+    /// Response statuses that trigger a retry
+    #[serde(default = "default_statuses")]
+    pub statuses: Vec<u16>,
+    /// Whether connection errors and other failures to get a response trigger a retry
+    #[serde(default = "default_transport_errors")]
+    pub transport_errors: bool,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_delay_ms() -> u64 {
+    500
+}
+
+fn default_statuses() -> Vec<u16> {
+    vec![429, 502, 503, 504]
+}
+
+fn default_transport_errors() -> bool {
+    true
+}
+
+impl RetryPolicy {
+    /// A policy that never retries, used for requests without a `retry` table
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff: Backoff::Fixed,
+            delay_ms: 0,
+            max_delay_ms: None,
+            jitter: false,
+            statuses: Vec::new(),
+            transport_errors: false,
+        }
+    }
+
+    pub fn retries_status(&self, status: StatusCode) -> bool {
+        self.statuses.contains(&status.as_u16())
+    }
+
+    /// How long to wait after the given (1-based) failed attempt
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let delay_ms = match self.backoff {
+            Backoff::Fixed => self.delay_ms,
+            Backoff::Exponential => self
+                .delay_ms
+                .saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1))),
+        };
+        let delay_ms = self
+            .max_delay_ms
+            .map_or(delay_ms, |max_delay_ms| delay_ms.min(max_delay_ms));
+        let delay_ms = if self.jitter && delay_ms > 0 {
+            rand::thread_rng().gen_range(delay_ms / 2..=delay_ms)
+        } else {
+            delay_ms
+        };
+
+        debug!(attempt, delay_ms, "Computed retry delay");
+        Duration::from_millis(delay_ms)
+    }
+
+    /// How long to wait before retrying a response after the given failed
+    /// attempt: its `Retry-After`, or else the computed delay. `None` if the
+    /// server asks for a longer wait than `max_delay_ms` allows, since a
+    /// retry any sooner would only be turned away again.
+    pub fn delay_after(&self, headers: &HeaderMap, attempt: u32) -> Option<Duration> {
+        let Some(delay) = retry_after(headers) else {
+            return Some(self.delay_for(attempt));
+        };
+        match self.max_delay_ms {
+            Some(max_delay_ms) if delay > Duration::from_millis(max_delay_ms) => {
+                debug!(
+                    ?delay,
+                    max_delay_ms, "Retry-After exceeds the maximum delay"
+                );
+                None
+            }
+            _ => Some(delay),
+        }
+    }
+}
+
+/// Parse a `Retry-After` header, given either in seconds or as an HTTP date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exponential_backoff_is_capped() {
+        let policy: RetryPolicy = toml::from_str(
+            r#"
+            backoff = "Exponential"
+            delay_ms = 100
+            max_delay_ms = 300
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(policy.max_attempts, 3);
+        assert_eq!(policy.delay_for(1), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(2), Duration::from_millis(200));
+        assert_eq!(policy.delay_for(3), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_retry_after_seconds_and_dates() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "7".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+        headers.insert(RETRY_AFTER, "soon".parse().unwrap());
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[test]
+    fn test_retry_after_is_capped_by_max_delay() {
+        let policy: RetryPolicy = toml::from_str(
+            r#"
+            backoff = "Fixed"
+            delay_ms = 100
+            max_delay_ms = 5000
+            "#,
+        )
+        .unwrap();
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            policy.delay_after(&headers, 1),
+            Some(Duration::from_millis(100))
+        );
+
+        headers.insert(RETRY_AFTER, "5".parse().unwrap());
+        assert_eq!(
+            policy.delay_after(&headers, 1),
+            Some(Duration::from_secs(5))
+        );
+
+        headers.insert(RETRY_AFTER, "3600".parse().unwrap());
+        assert_eq!(policy.delay_after(&headers, 1), None);
+
+        // Without a maximum, the server decides
+        let policy = RetryPolicy::none();
+        assert_eq!(
+            policy.delay_after(&headers, 1),
+            Some(Duration::from_secs(3600))
+        );
+    }
+}
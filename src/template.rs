@@ -0,0 +1,370 @@
+use std::fmt;
+use thiserror::Error;
+
+/// A syntax error in a template, pointing at where it was found
+#[derive(Debug, Error)]
+pub struct TemplateError {
+    pub template: String,
+    /// Character offset of the problem
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Invalid template at position {}: {}",
+            self.position, self.message
+        )?;
+        writeln!(f, "  {}", self.template)?;
+        write!(f, "  {}^", " ".repeat(self.position))
+    }
+}
+
+/// A string with placeholders, such as `Bearer {token}`.
+///
+/// Placeholders are written `{name}`, where names may contain letters,
+/// digits, `_`, `.` and `-`, or `{$generator}`. A name can be followed by a
+/// default (`{name:-fallback}`) or marked optional (`{name?}`), and then
+/// piped through filters (`{name | trim | base64}`). A `{` that isn't
+/// directly followed by a name, like those of `{"id": 1}` or `a { b }`, is
+/// kept as text, and so is a `}` outside of a placeholder. Where a brace
+/// would start a placeholder, `{{` is a literal `{`, and the `}}` that
+/// closes it a literal `}`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    pub segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Placeholder {
+    /// The name, or the whole expression of a generator like `$now + 1d`
+    pub name: String,
+    pub default: Option<String>,
+    pub optional: bool,
+    pub filters: Vec<String>,
+    /// The placeholder as written, braces included
+    pub source: String,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, TemplateError> {
+        Parser {
+            template,
+            chars: template.chars().collect(),
+            position: 0,
+        }
+        .parse()
+    }
+
+    pub fn placeholders(&self) -> impl Iterator<Item = &Placeholder> {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Placeholder(placeholder) => Some(placeholder),
+            Segment::Literal(_) => None,
+        })
+    }
+
+    /// The placeholder, if the template is nothing but a single placeholder
+    pub fn as_placeholder(&self) -> Option<&Placeholder> {
+        match self.segments.as_slice() {
+            [Segment::Placeholder(placeholder)] => Some(placeholder),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    template: &'a str,
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, position: usize, message: &str) -> TemplateError {
+        TemplateError {
+            template: self.template.to_string(),
+            position,
+            message: message.to_string(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    /// Skip whitespace, but only if `expected` comes after it. Spacing is
+    /// only allowed around `|` and `:-`, so that `{name }` isn't mistaken
+    /// for a placeholder.
+    fn skip_whitespace_before(&mut self, expected: &str) {
+        let mut position = self.position;
+        while self.chars.get(position).is_some_and(|c| c.is_whitespace()) {
+            position += 1;
+        }
+        if expected
+            .chars()
+            .enumerate()
+            .all(|(offset, c)| self.chars.get(position + offset) == Some(&c))
+        {
+            self.position = position;
+        }
+    }
+
+    /// Consume characters while `predicate` holds and return them
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let start = self.position;
+        while self.peek().is_some_and(&predicate) {
+            self.position += 1;
+        }
+        self.chars[start..self.position].iter().collect()
+    }
+
+    fn parse(mut self) -> Result<Template, TemplateError> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        // `{{` escapes still waiting for their `}}`. Other closing braces are
+        // kept as they are, so `{"a":{"b":{id}}}` keeps all of its own.
+        let mut escaped = 0;
+
+        while let Some(c) = self.peek() {
+            let next = self.chars.get(self.position + 1).copied();
+            match (c, next) {
+                ('{', Some('{')) => {
+                    literal.push(c);
+                    self.position += 2;
+                    escaped += 1;
+                }
+                ('}', Some('}')) if escaped > 0 => {
+                    literal.push(c);
+                    self.position += 2;
+                    escaped -= 1;
+                }
+                ('{', _) if self.starts_placeholder() => {
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Placeholder(self.parse_placeholder()?));
+                }
+                _ => {
+                    literal.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Template { segments })
+    }
+
+    /// Whether the `{` at the current position is directly followed by a
+    /// name or a generator. Anything else, like `{ word }`, is just a brace.
+    fn starts_placeholder(&self) -> bool {
+        self.chars
+            .get(self.position + 1)
+            .is_some_and(|&c| c.is_alphanumeric() || matches!(c, '_' | '.' | '-' | '$'))
+    }
+
+    fn parse_placeholder(&mut self) -> Result<Placeholder, TemplateError> {
+        let start = self.position;
+        self.position += 1; // {
+
+        let name_position = self.position;
+        let name = if self.peek() == Some('$') {
+            // Generators have their own syntax, checked when they run
+            self.take_while(|c| c != '|' && c != '}')
+                .trim_end()
+                .to_string()
+        } else {
+            self.take_while(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        };
+        if name.is_empty() || name == "$" {
+            return Err(self.error(
+                name_position,
+                "expected a placeholder name (write `{{` for a literal brace)",
+            ));
+        }
+
+        let mut default = None;
+        let mut optional = false;
+        self.skip_whitespace_before(":-");
+        match (self.peek(), self.chars.get(self.position + 1)) {
+            (Some(':'), Some('-')) => {
+                self.position += 2;
+                let fallback = self.take_while(|c| !matches!(c, '|' | '{' | '}'));
+                default = Some(fallback.trim().to_string());
+            }
+            (Some('?'), _) => {
+                self.position += 1;
+                optional = true;
+            }
+            _ => {}
+        }
+
+        let mut filters = Vec::new();
+        self.skip_whitespace_before("|");
+        while self.peek() == Some('|') {
+            self.position += 1;
+            self.skip_whitespace();
+            let filter_position = self.position;
+            let filter = self.take_while(|c| c.is_alphanumeric() || c == '_');
+            if filter.is_empty() {
+                return Err(self.error(filter_position, "expected a filter name after `|`"));
+            }
+            filters.push(filter);
+            self.skip_whitespace_before("|");
+        }
+
+        match self.peek() {
+            Some('}') => self.position += 1,
+            Some(c) if c.is_whitespace() => {
+                return Err(self.error(
+                    self.position,
+                    &format!(
+                        "unexpected space in placeholder `{}` (spaces are only allowed around `|` and `:-`)",
+                        name
+                    ),
+                ))
+            }
+            Some(c) => {
+                return Err(self.error(
+                    self.position,
+                    &format!("unexpected `{}` in placeholder `{}`", c, name),
+                ))
+            }
+            None => {
+                return Err(self.error(
+                    start,
+                    "unclosed placeholder (write `{{` for a literal brace)",
+                ))
+            }
+        }
+
+        Ok(Placeholder {
+            name,
+            default,
+            optional,
+            filters,
+            source: self.chars[start..self.position].iter().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placeholder(name: &str, source: &str) -> Placeholder {
+        Placeholder {
+            name: name.to_string(),
+            default: None,
+            optional: false,
+            filters: Vec::new(),
+            source: source.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_templates() {
+        let template = Template::parse("Bearer {github.token} {{literal}} {api-key}").unwrap();
+        assert_eq!(
+            template.segments,
+            [
+                Segment::Literal("Bearer ".to_string()),
+                Segment::Placeholder(placeholder("github.token", "{github.token}")),
+                Segment::Literal(" {literal} ".to_string()),
+                Segment::Placeholder(placeholder("api-key", "{api-key}")),
+            ]
+        );
+
+        let template = Template::parse("{sort :- created at | trim|upper}").unwrap();
+        let sort = template.as_placeholder().unwrap();
+        assert_eq!(sort.name, "sort");
+        assert_eq!(sort.default.as_deref(), Some("created at"));
+        assert_eq!(sort.filters, ["trim", "upper"]);
+
+        assert!(
+            Template::parse("{q?}")
+                .unwrap()
+                .as_placeholder()
+                .unwrap()
+                .optional
+        );
+        assert_eq!(
+            Template::parse("{$now + 1d | upper}")
+                .unwrap()
+                .as_placeholder()
+                .unwrap()
+                .name,
+            "$now + 1d"
+        );
+    }
+
+    #[test]
+    fn test_braces_that_are_not_placeholders_are_text() {
+        for text in [
+            "a } b",
+            "a { b }",
+            "{}",
+            "{ }",
+            "}}",
+            r#"{"id": 1}"#,
+            r#"{"query": "query { viewer { login } }"}"#,
+        ] {
+            assert_eq!(
+                Template::parse(text).unwrap().segments,
+                [Segment::Literal(text.to_string())]
+            );
+        }
+
+        // Closing braces after a placeholder are the body's own
+        let template = Template::parse(r#"{"a":{"b":{id}}}"#).unwrap();
+        assert_eq!(
+            template.segments,
+            [
+                Segment::Literal(r#"{"a":{"b":"#.to_string()),
+                Segment::Placeholder(placeholder("id", "{id}")),
+                Segment::Literal("}}".to_string()),
+            ]
+        );
+
+        let template = Template::parse("{{ viewer {{ login }} }} {{x}}").unwrap();
+        assert_eq!(
+            template.segments,
+            [Segment::Literal("{ viewer { login } } {x}".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_errors_point_at_the_problem() {
+        let error = Template::parse("/users/{bad name}").unwrap_err();
+        assert_eq!(error.position, 11);
+        assert_eq!(
+            error.to_string(),
+            "Invalid template at position 11: unexpected space in placeholder `bad` (spaces are only allowed around `|` and `:-`)\n  /users/{bad name}\n             ^"
+        );
+
+        assert_eq!(Template::parse("x {token").unwrap_err().position, 2);
+        assert_eq!(Template::parse("{token }").unwrap_err().position, 6);
+        assert_eq!(
+            Template::parse("{token | upper }").unwrap_err().position,
+            14
+        );
+        assert_eq!(Template::parse("{$}").unwrap_err().position, 1);
+        assert_eq!(Template::parse("{token!}").unwrap_err().position, 6);
+        assert_eq!(Template::parse("{token | }").unwrap_err().position, 9);
+    }
+}
@@ -0,0 +1,345 @@
+use crate::options::{VaultCommand, VaultOptions};
+use crate::private_file;
+use argon2::{Argon2, Params};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use dialoguer::{theme::ColorfulTheme, Password};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tracing::info;
+
+/// The environment variable the passphrase is read from before prompting
+pub const PASSPHRASE_VAR: &str = "GLINT_VAULT_PASSPHRASE";
+
+const VERSION: u32 = 1;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+
+#[derive(Debug, Error)]
+pub enum VaultError {
+    #[error("No vault at {path:?}, create one with `glint vault init`")]
+    NotInitialized { path: PathBuf },
+    #[error("A vault already exists at {path:?}")]
+    AlreadyExists { path: PathBuf },
+    #[error("Wrong passphrase for the vault at {path:?}")]
+    WrongPassphrase { path: PathBuf },
+    #[error("The vault at {path:?} is corrupt: {message}")]
+    Corrupt { path: PathBuf, message: String },
+    #[error("Key `{key}` is not in the vault")]
+    KeyNotFound { key: String },
+    #[error("Failed to access the vault at {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Reading the passphrase failed")]
+    PassphraseFailed,
+    #[error("Reading the value failed")]
+    ValueFailed,
+    #[error("Deriving the vault key failed: {0}")]
+    KeyDerivation(String),
+}
+
+/// The vault as stored on disk. Values and key names are encrypted together,
+/// so the file doesn't reveal what it holds.
+#[derive(Deserialize, Serialize)]
+struct VaultFile {
+    version: u32,
+    /// Argon2id parameters, kept so they can be raised for new vaults
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// A passphrase-protected store of secrets, encrypted with
+/// XChaCha20-Poly1305 under a key derived from the passphrase with Argon2id
+pub struct Vault {
+    path: PathBuf,
+    params: Params,
+    salt: Vec<u8>,
+    key: [u8; 32],
+    entries: BTreeMap<String, String>,
+}
+
+impl std::fmt::Debug for Vault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Vault").field("path", &self.path).finish()
+    }
+}
+
+/// Where the vault lives unless `--vault` or `GLINT_VAULT` says otherwise
+pub fn default_path() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_default()
+        .join(".glint")
+        .join("vault")
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: Params) -> Result<[u8; 32], VaultError> {
+    let mut key = [0; 32];
+    Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|error| VaultError::KeyDerivation(error.to_string()))?;
+    Ok(key)
+}
+
+impl Vault {
+    /// Create an empty vault. It isn't written until it is saved.
+    pub fn create(path: &Path, passphrase: &str) -> Result<Self, VaultError> {
+        if path.exists() {
+            return Err(VaultError::AlreadyExists {
+                path: path.to_path_buf(),
+            });
+        }
+
+        let params = Params::default();
+        let mut salt = vec![0; SALT_LENGTH];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let key = derive_key(passphrase, &salt, params.clone())?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            params,
+            salt,
+            key,
+            entries: BTreeMap::new(),
+        })
+    }
+
+    pub fn open(path: &Path, passphrase: &str) -> Result<Self, VaultError> {
+        let corrupt = |message: &str| VaultError::Corrupt {
+            path: path.to_path_buf(),
+            message: message.to_string(),
+        };
+
+        let content = fs::read_to_string(path).map_err(|source| match source.kind() {
+            std::io::ErrorKind::NotFound => VaultError::NotInitialized {
+                path: path.to_path_buf(),
+            },
+            _ => VaultError::Io {
+                path: path.to_path_buf(),
+                source,
+            },
+        })?;
+        let file: VaultFile =
+            serde_json::from_str(&content).map_err(|error| corrupt(&error.to_string()))?;
+        if file.version != VERSION {
+            return Err(corrupt(&format!("unsupported version {}", file.version)));
+        }
+
+        let decode = |field: &str| {
+            STANDARD
+                .decode(field)
+                .map_err(|_| corrupt("invalid base64"))
+        };
+        let salt = decode(&file.salt)?;
+        let nonce = decode(&file.nonce)?;
+        let ciphertext = decode(&file.ciphertext)?;
+        if nonce.len() != NONCE_LENGTH {
+            return Err(corrupt("invalid nonce"));
+        }
+
+        let params = Params::new(file.m_cost, file.t_cost, file.p_cost, None)
+            .map_err(|error| corrupt(&error.to_string()))?;
+        let key = derive_key(passphrase, &salt, params.clone())?;
+        let plaintext = XChaCha20Poly1305::new(&key.into())
+            .decrypt(XNonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| VaultError::WrongPassphrase {
+                path: path.to_path_buf(),
+            })?;
+        let entries =
+            serde_json::from_slice(&plaintext).map_err(|error| corrupt(&error.to_string()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            params,
+            salt,
+            key,
+            entries,
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(String::as_str)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.entries.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<(), VaultError> {
+        self.entries
+            .remove(key)
+            .map(drop)
+            .ok_or_else(|| VaultError::KeyNotFound {
+                key: key.to_string(),
+            })
+    }
+
+    /// Key names, sorted
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// Encrypt the vault with a fresh nonce and write it. The file is
+    /// replaced in one step and is only readable by its owner.
+    pub fn save(&self) -> Result<(), VaultError> {
+        let mut nonce = [0; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let plaintext = serde_json::to_vec(&self.entries).expect("entries are serializable");
+        let ciphertext = XChaCha20Poly1305::new(&self.key.into())
+            .encrypt(XNonce::from_slice(&nonce), plaintext.as_slice())
+            .expect("encrypting in memory can't fail");
+
+        let file = VaultFile {
+            version: VERSION,
+            m_cost: self.params.m_cost(),
+            t_cost: self.params.t_cost(),
+            p_cost: self.params.p_cost(),
+            salt: STANDARD.encode(&self.salt),
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+        };
+        let content = serde_json::to_string_pretty(&file).expect("the vault is serializable");
+        private_file::write(&self.path, content.as_bytes()).map_err(|source| VaultError::Io {
+            path: self.path.clone(),
+            source,
+        })?;
+
+        info!("Saved vault to {:?}", self.path);
+        Ok(())
+    }
+}
+
+/// Read the passphrase from `GLINT_VAULT_PASSPHRASE`, or ask for it. New
+/// passphrases are asked for twice.
+pub fn passphrase(confirm: bool) -> Result<String, VaultError> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase);
+    }
+
+    let theme = ColorfulTheme::default();
+    let mut prompt = Password::with_theme(&theme).with_prompt("Vault passphrase");
+    if confirm {
+        prompt = prompt.with_confirmation("Confirm passphrase", "Passphrases don't match");
+    }
+    prompt.interact().map_err(|_| VaultError::PassphraseFailed)
+}
+
+/// Read a value to store: piped in on standard input, or asked for without
+/// echoing it
+fn read_value(key: &str) -> Result<String, VaultError> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        return Password::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Value for {}", key))
+            .interact()
+            .map_err(|_| VaultError::ValueFailed);
+    }
+
+    let mut value = String::new();
+    stdin
+        .lock()
+        .read_to_string(&mut value)
+        .map_err(|_| VaultError::ValueFailed)?;
+    // Drop the newline `echo` adds, but nothing else
+    let value = value.strip_suffix('\n').unwrap_or(&value);
+    Ok(value.strip_suffix('\r').unwrap_or(value).to_string())
+}
+
+/// Run a `glint vault` subcommand
+pub fn run(options: &VaultOptions, path: &Path) -> Result<(), VaultError> {
+    match &options.command {
+        VaultCommand::Init => {
+            Vault::create(path, &passphrase(true)?)?.save()?;
+            eprintln!("Created a vault at {}", path.display());
+        }
+        VaultCommand::Set { key } => {
+            let mut vault = Vault::open(path, &passphrase(false)?)?;
+            let value = read_value(key)?;
+            vault.set(key, &value);
+            vault.save()?;
+        }
+        VaultCommand::Get { key } => {
+            let vault = Vault::open(path, &passphrase(false)?)?;
+            let value = vault.get(key).ok_or_else(|| VaultError::KeyNotFound {
+                key: key.to_string(),
+            })?;
+            println!("{}", value);
+        }
+        VaultCommand::List => {
+            for key in Vault::open(path, &passphrase(false)?)?.keys() {
+                println!("{}", key);
+            }
+        }
+        VaultCommand::Rm { key } => {
+            let mut vault = Vault::open(path, &passphrase(false)?)?;
+            vault.remove(key)?;
+            vault.save()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vault_round_trip() {
+        let directory = std::env::temp_dir().join(format!("glint-vault-{}", std::process::id()));
+        let path = directory.join("vault");
+        let _ = fs::remove_dir_all(&directory);
+
+        let mut vault = Vault::create(&path, "correct horse").unwrap();
+        vault.set("github_token", "ghp_secret");
+        vault.set("api_key", "k");
+        vault.save().unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("ghp_secret") && !content.contains("github_token"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let mut vault = Vault::open(&path, "correct horse").unwrap();
+        assert_eq!(vault.get("github_token"), Some("ghp_secret"));
+        assert_eq!(
+            vault.keys().collect::<Vec<_>>(),
+            ["api_key", "github_token"]
+        );
+        vault.remove("api_key").unwrap();
+        assert!(matches!(
+            vault.remove("api_key"),
+            Err(VaultError::KeyNotFound { .. })
+        ));
+
+        assert!(matches!(
+            Vault::open(&path, "wrong"),
+            Err(VaultError::WrongPassphrase { .. })
+        ));
+        assert!(matches!(
+            Vault::create(&path, "again"),
+            Err(VaultError::AlreadyExists { .. })
+        ));
+        assert!(matches!(
+            Vault::open(&directory.join("missing"), "x"),
+            Err(VaultError::NotInitialized { .. })
+        ));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}